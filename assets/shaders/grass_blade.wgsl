@@ -0,0 +1,124 @@
+#define_import_path grass::blade
+
+// Everything needed to build a blade, written by the compute pass.
+// Expanded into vertices by the compute pass or by the vertex shader in the instanced mode.
+struct BladeInstance {
+    // root position + blade height
+    position_height: vec4<f32>,
+    // surface normal + blade width
+    normal_width: vec4<f32>,
+    // tip displacement + lean of the tip
+    displacement_forward: vec4<f32>,
+    // rotation angle, curve, bottom width, offset from the root
+    shape: vec4<f32>,
+    // base color, tip color, segments, opacity as f32 bits
+    data: vec4<u32>,
+}
+
+struct BladeVertex {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    // normal of the bent blade, its sign is left to the fragment shader
+    face: vec3<f32>,
+    uv: vec2<f32>,
+    // linear color
+    color: vec3<f32>,
+    // distance fade, dissolved by the fragment shader
+    opacity: f32,
+}
+
+// A function to compute an rotation matrix which rotates a point
+// by angle radians around the given axis
+// By Keijiro Takahashi
+fn angle_axis_3x3(angle: f32, axis: vec3<f32>) -> mat3x3<f32> {
+    // float c, s; sincos(angle, s, c);
+    let s = sin(angle);
+    let c = cos(angle);
+
+    let s = axis * s;
+    let t = axis * (1.0 - c);
+
+    return mat3x3<f32>(
+        t.x * axis.x + c  , t.y * axis.x - s.z, t.z * axis.x + s.y,
+        t.x * axis.y + s.z, t.y * axis.y + c  , t.z * axis.y - s.x,
+        t.x * axis.z - s.y, t.y * axis.z + s.x, t.z * axis.z + c ,
+    );
+}
+
+// Orthonormal basis with the y axis along the given surface normal,
+// so blades built in a y-up local space grow out of slopes, walls and ceilings
+fn surface_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    let helper = select(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.z) > 0.999);
+    let tangent = normalize(cross(normal, helper));
+    let bitangent = cross(tangent, normal);
+    return mat3x3<f32>(tangent, normal, bitangent);
+}
+
+// colors are stored as the square root of the linear color, so the dark greens keep their precision
+fn pack_color(color: vec3<f32>, alpha: f32) -> u32 {
+    return pack4x8unorm(vec4<f32>(sqrt(max(color, vec3<f32>(0.0))), alpha));
+}
+
+fn unpack_color(packed: u32) -> vec3<f32> {
+    let color = unpack4x8unorm(packed).rgb;
+    return color * color;
+}
+
+// Vertex `index` of the triangle strip of a blade, two per segment followed by the tip.
+// Indices past the tip collapse into it, so blades with fewer segments can share a draw.
+fn blade_vertex(blade: BladeInstance, index: u32) -> BladeVertex {
+    // blades are rotated around the (slightly tilted) local up axis
+    // and then placed into the frame of the surface
+    let rotation_axis = vec3<f32>(0.0, 1.0, -0.1);
+    //let rotation_axis = vec3<f32>(-0.1, 0.0, 1.0);
+    let rotation = angle_axis_3x3(blade.shape.x, rotation_axis);
+
+    let position = blade.position_height.xyz;
+    let normal = blade.normal_width.xyz;
+    let frame = surface_frame(normal);
+    let displacement = blade.displacement_forward.xyz;
+    let offset = blade.shape.w;
+
+    let segments = blade.data.z;
+    let base_color = unpack_color(blade.data.x);
+    let tip_color = unpack_color(blade.data.y);
+
+    var out: BladeVertex;
+    out.normal = normal;
+    out.opacity = bitcast<f32>(blade.data.w);
+
+    // across the blade and along its curve, at the height of the vertex
+    let t = min(f32(index / 2u) / f32(segments), 1.0);
+    let across = frame * (vec3<f32>(1.0, 0.0, 0.0) * rotation);
+    let bend = blade.shape.y * pow(max(t, 0.05), blade.shape.y - 1.0) * blade.displacement_forward.w;
+    let along = frame * (vec3<f32>(0.0, blade.position_height.w, bend) * rotation) + displacement;
+    out.face = normalize(cross(along, across));
+
+    // top vertex
+    if (index >= segments * 2u) {
+        let local = vec3<f32>(0.0, blade.position_height.w, offset + blade.displacement_forward.w);
+        out.position = position + displacement + frame * (local * rotation);
+        out.uv = vec2<f32>(0.5, 1.0);
+        out.color = tip_color;
+        return out;
+    }
+
+    let segment = index / 2u;
+    let side = index % 2u;
+    let taper_width = f32(segment) / f32(segments);
+
+    // the first segment is thinner
+    let first_thinner = select(1.0, blade.shape.z, segment == 0u);
+    let width = blade.normal_width.w * (1.0 - taper_width) * first_thinner * select(1.0, -1.0, side == 1u);
+    let height = blade.position_height.w * taper_width;
+
+    let forward = offset + pow(abs(taper_width), blade.shape.y) * blade.displacement_forward.w;
+
+    // first grass (0) segment does not get displaced by interactor
+    let translation = position + select(displacement * taper_width, vec3<f32>(0.0), segment == 0u);
+
+    out.position = translation + frame * (vec3<f32>(width, height, forward) * rotation);
+    out.uv = vec2<f32>(f32(side), taper_width);
+    out.color = mix(base_color, tip_color, taper_width);
+    return out;
+}
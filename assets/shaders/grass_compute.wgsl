@@ -1,74 +1,449 @@
 // compute shader
 
+#import grass::blade
+
 struct Params {
     time: f32,
     length: u32,
 
     blades: u32,
+    // segments of the blades at full detail
+    segments: u32,
     blade_radius: f32,
-    blade_forward: f32,
-    blade_curve: f32,
+    // tallest blade and furthest lean of all species
+    max_height: f32,
+    max_forward: f32,
 
-    wind_speed: f32,
+    // how strongly this field responds to the wind
     wind_strength: f32,
+
+    model: mat4x4<f32>,
+
+    frustum_culling: u32,
+
+    lod_count: u32,
+    lod_fade: f32,
+    // in the padding before the lod bands
+    occlusion_culling: u32,
+    lod_bands: array<LodBand, 4u>,
+
+    trampling: u32,
+    // fraction of a full trample recovered per second
+    trample_recovery: f32,
+    // fraction of the full blade height regrown per second
+    regrowth: f32,
+    pad_trample: u32,
+
+    // largest per-blade hue shift in radians
+    hue_variation: f32,
+    value_variation: f32,
+    // size of one color map tile in world units
+    color_map_scale: f32,
+
+    seed: u32,
+
+    species_count: u32,
+    // write blade instances instead of vertices
+    instanced: u32,
+    species: array<Species, 4u>,
+
+    // size of the output buffers, blades past it are dropped
+    capacity_blades: u32,
+    capacity_vertices: u32,
+    capacity_indices: u32,
+    pad_capacity: u32,
+
+    // blades dissolve between fade_start and fade_end from the closest camera
+    fade: u32,
+    fade_start: f32,
+    fade_end: f32,
+    // roots culled for the cameras are kept inside the frustums of the shadow casting lights
+    cast_shadows: u32,
+}
+
+struct Species {
+    base_color: vec4<f32>,
+    tip_color: vec4<f32>,
+    height_min: f32,
+    height_max: f32,
+    width_min: f32,
+    width_max: f32,
+    // width of the first segment relative to the rest of the blade
+    bottom_width: f32,
+    forward: f32,
+    curve: f32,
+    weight: f32,
+}
+
+struct LodBand {
+    // roots closer than this to a camera use this band
+    distance: f32,
+    segments: u32,
+    blades: u32,
+    pad: u32,
+}
+
+struct Views {
+    count: u32,
+    // the depth pyramid of the previous frame can be tested against
+    occlusion: u32,
+    // shadow casting lights, their frustums follow the ones of the cameras
+    shadow_count: u32,
+    // frustum planes as (normal, distance), six per view
+    planes: array<vec4<f32>, 72u>,
+    positions: array<vec4<f32>, 4u>,
+    // view projection the depth pyramid was rendered with
+    occlusion_view_proj: mat4x4<f32>,
+}
+
+struct Wind {
+    direction: vec3<f32>,
+    strength: f32,
+    speed: f32,
+
+    gust_frequency: f32,
+    gust_amplitude: f32,
+
+    turbulence_scale: f32,
+    turbulence_strength: f32,
+}
+
+struct Interactor {
+    position: vec3<f32>,
+    radius: f32,
+    strength: f32,
+}
+
+struct Interactors {
+    count: u32,
+    data: array<Interactor>,
+}
+
+struct Cut {
+    // 0 sphere, 1 box
+    shape: u32,
+    // fraction of the full blade height left standing
+    height: f32,
+    // center and radius of spheres
+    center: vec4<f32>,
+    half_extents: vec4<f32>,
+    // inverse rotation of boxes
+    rotation: vec4<f32>,
+}
+
+struct Cuts {
+    count: u32,
+    data: array<Cut>,
+}
+
+struct DstVertex {
+    // position + normal + blade face normal + uvs
+    data: array<f32, 11>,
+    // square root of the linear color as unorm8x4
+    color: u32,
+}
+
+// reservations may run past the capacity, the host grows the buffers when blades are dropped
+struct DstCounters {
+    vertices: atomic<u32>,
+    blades: atomic<u32>,
+    indices: atomic<u32>,
+    dropped: atomic<u32>,
 }
 
 struct DrawIndexedIndirect {
     vertex_count: atomic<u32>,
-    instance_count: u32,
+    instance_count: atomic<u32>,
     base_index: u32,
     vertex_offset: i32,
     base_instance: u32,
 }
 
 @group(0) @binding(0) var<uniform>             params: Params;
-@group(0) @binding(1) var<storage, read>       src_vertices: array<array<f32, 6>>; // position + normal
-@group(0) @binding(2) var<storage, read_write> dst_vertices: array<array<f32, 8>>; // position + normal + uvs
-@group(0) @binding(3) var<storage, read_write> dst_vertices_count: atomic<u32>;
+@group(0) @binding(1) var<storage, read>       src_vertices: array<array<f32, 8>>; // position + normal + uvs
+@group(0) @binding(2) var<storage, read_write> dst_vertices: array<DstVertex>;
+@group(0) @binding(3) var<storage, read_write> dst_counters: DstCounters;
 @group(0) @binding(4) var<storage, read_write> dst_indirect: DrawIndexedIndirect;
+@group(0) @binding(5) var density_map: texture_2d<f32>;
+@group(0) @binding(6) var density_sampler: sampler;
+@group(0) @binding(7) var<uniform>             views: Views;
+@group(0) @binding(8) var<storage, read_write> dst_indices: array<u32>;
+@group(0) @binding(9) var<storage, read>       interactors: Interactors;
+@group(0) @binding(10) var<storage, read_write> trample: array<vec4<f32>>; // push direction + time, per root
+@group(0) @binding(11) var<uniform>             wind: Wind;
+@group(0) @binding(12) var wind_turbulence: texture_2d<f32>;
+@group(0) @binding(13) var wind_sampler: sampler;
+@group(0) @binding(14) var color_map: texture_2d<f32>;
+@group(0) @binding(15) var color_sampler: sampler;
+@group(0) @binding(16) var<storage, read>       cuts: Cuts;
+@group(0) @binding(17) var<storage, read_write> cut_state: array<vec2<f32>>; // cut amount + time, per root
+@group(0) @binding(18) var species_map: texture_2d<f32>;
+@group(0) @binding(19) var species_sampler: sampler;
+@group(0) @binding(20) var<storage, read_write> dst_instances: array<BladeInstance>;
+@group(0) @binding(21) var depth_pyramid: texture_2d<f32>; // farthest depth below each texel
 
 @compute @workgroup_size(1, 1, 1)
 fn cs_main_init() {
-    atomicStore(&dst_vertices_count, 0u);
-    atomicStore(&dst_indirect.vertex_count, 0u);
-    dst_indirect.instance_count = 1u;
+    atomicStore(&dst_counters.vertices, 0u);
+    atomicStore(&dst_counters.blades, 0u);
+    atomicStore(&dst_counters.indices, 0u);
+    atomicStore(&dst_counters.dropped, 0u);
+    if (params.instanced != 0u) {
+        // one strip per instance, the indices are static
+        atomicStore(&dst_indirect.vertex_count, params.segments * 2u + 1u);
+        atomicStore(&dst_indirect.instance_count, 0u);
+    } else {
+        atomicStore(&dst_indirect.vertex_count, 0u);
+        atomicStore(&dst_indirect.instance_count, 1u);
+    }
     dst_indirect.base_index = 0u;
     dst_indirect.vertex_offset = 0;
     dst_indirect.base_instance = 0u;
 }
 
-fn set_vertex(index: u32, normal: vec3<f32>, position: vec3<f32>, texcoord: vec2<f32>) {
-    dst_vertices[index] = array<f32, 8>(
-        position.x, position.y, position.z,
-        normal.x, normal.y, normal.z,
-        texcoord.x, texcoord.y,
+fn set_vertex(index: u32, vertex: BladeVertex) {
+    dst_vertices[index] = DstVertex(
+        array<f32, 11>(
+            vertex.position.x, vertex.position.y, vertex.position.z,
+            vertex.normal.x, vertex.normal.y, vertex.normal.z,
+            vertex.face.x, vertex.face.y, vertex.face.z,
+            vertex.uv.x, vertex.uv.y,
+        ),
+        pack_color(vertex.color, vertex.opacity),
     );
 }
 
-fn face_normal(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> vec3<f32> {
-    return normalize(cross(b - a, c - a));
+// rotates the color around the gray axis, keeping its brightness
+fn hue_shift(color: vec3<f32>, angle: f32) -> vec3<f32> {
+    let k = vec3<f32>(0.57735);
+    let c = cos(angle);
+    return color * c + cross(k, color) * sin(angle) + k * dot(k, color) * (1.0 - c);
 }
 
-// A function to compute an rotation matrix which rotates a point
-// by angle radians around the given axis
-// By Keijiro Takahashi
-fn angle_axis_3x3(angle: f32, axis: vec3<f32>) -> mat3x3<f32> {
-    // float c, s; sincos(angle, s, c);
-    let s = sin(angle);
-    let c = cos(angle);
+// true if the sphere touches the frustum `frustum` of the views
+fn in_frustum(frustum: u32, center: vec3<f32>, radius: f32) -> bool {
+    var plane_index = 0u;
+    loop {
+        if (plane_index >= 6u) { break; }
+        let plane = views.planes[frustum * 6u + plane_index];
+        if (dot(plane.xyz, center) + plane.w + radius <= 0.0) {
+            return false;
+        }
+        continuing { plane_index += 1u; }
+    }
+    return true;
+}
+
+// true if the sphere touches the frustum of at least one camera
+fn is_visible(center: vec3<f32>, radius: f32) -> bool {
+    if (views.count == 0u) {
+        return true;
+    }
+
+    var view_index = 0u;
+    loop {
+        if (view_index >= views.count) { break; }
+        if (in_frustum(view_index, center, radius)) {
+            return true;
+        }
+        continuing { view_index += 1u; }
+    }
+
+    return false;
+}
+
+// true if the sphere touches the frustum of at least one shadow casting light
+fn casts_shadow(center: vec3<f32>, radius: f32) -> bool {
+    var light_index = 0u;
+    loop {
+        if (light_index >= views.shadow_count) { break; }
+        if (in_frustum(views.count + light_index, center, radius)) {
+            return true;
+        }
+        continuing { light_index += 1u; }
+    }
+
+    return false;
+}
+
+// true if the sphere is behind the depth of the previous frame
+fn is_occluded(center: vec3<f32>, radius: f32) -> bool {
+    if (views.occlusion == 0u) {
+        return false;
+    }
+
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 0.0;
 
-    let s = axis * s;
-    let t = axis * (1.0 - c);
+    // screen bounds and closest depth of the corners of the box around the sphere
+    var corner = 0u;
+    loop {
+        if (corner >= 8u) { break; }
+        let offset = vec3<f32>(f32(corner & 1u), f32((corner >> 1u) & 1u), f32((corner >> 2u) & 1u)) * 2.0 - 1.0;
+        let clip = views.occlusion_view_proj * vec4<f32>(center + offset * radius, 1.0);
+        // crosses the near plane
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        // reverse z, larger is closer
+        nearest = max(nearest, ndc.z);
+        continuing { corner += 1u; }
+    }
+
+    // partly off screen last frame, nothing is known about the rest
+    if (any(uv_min < vec2<f32>(0.0)) || any(uv_max > vec2<f32>(1.0))) {
+        return false;
+    }
 
-    return mat3x3<f32>(
-        t.x * axis.x + c  , t.y * axis.x - s.z, t.z * axis.x + s.y,
-        t.x * axis.y + s.z, t.y * axis.y + c  , t.z * axis.y - s.x,
-        t.x * axis.z - s.y, t.y * axis.z + s.x, t.z * axis.z + c ,
+    let size = textureDimensions(depth_pyramid, 0);
+    let pixel_min = min(vec2<i32>(uv_min * vec2<f32>(size)), size - 1);
+    let pixel_max = min(vec2<i32>(uv_max * vec2<f32>(size)), size - 1);
+
+    // the level where the bounds cover at most 2x2 texels
+    let extent = vec2<f32>(pixel_max - pixel_min + 1);
+    let level = clamp(i32(ceil(log2(max(extent.x, extent.y)))), 0, textureNumLevels(depth_pyramid) - 1);
+    let level_size = textureDimensions(depth_pyramid, level);
+    let shift = vec2<u32>(u32(level));
+    // odd sizes fold their last row and column into the last texel
+    let texel_min = min(pixel_min >> shift, level_size - 1);
+    let texel_max = min(pixel_max >> shift, level_size - 1);
+
+    // farthest depth under the bounds, reverse z
+    let farthest = min(
+        min(
+            textureLoad(depth_pyramid, texel_min, level).r,
+            textureLoad(depth_pyramid, vec2<i32>(texel_max.x, texel_min.y), level).r,
+        ),
+        min(
+            textureLoad(depth_pyramid, vec2<i32>(texel_min.x, texel_max.y), level).r,
+            textureLoad(depth_pyramid, texel_max, level).r,
+        ),
     );
+
+    return nearest < farthest;
+}
+
+// distance to the closest camera
+fn view_distance(position: vec3<f32>) -> f32 {
+    var distance = 3.40282347e+38;
+
+    var view_index = 0u;
+    loop {
+        if (view_index >= views.count) { break; }
+        distance = min(distance, length(views.positions[view_index].xyz - position));
+        continuing { view_index += 1u; }
+    }
+
+    return distance;
+}
+
+// world space push of all interactors at a root, kept in the plane of the surface
+fn interactor_push(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var push = vec3<f32>(0.0);
+
+    var i = 0u;
+    loop {
+        if (i >= interactors.count) { break; }
+
+        let interactor = interactors.data[i];
+        let offset = position - interactor.position;
+        let distance = length(offset);
+        if (distance < interactor.radius) {
+            let away = offset - normal * dot(offset, normal);
+            let falloff = 1.0 - distance / interactor.radius;
+            push = push + normalize(away + vec3<f32>(0.0001)) * falloff * interactor.strength;
+        }
+
+        continuing { i += 1u; }
+    }
+
+    return push;
+}
+
+fn quat_rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+// largest cut of this frame at a world space position, as the fraction of the blade removed
+fn cut_at(position: vec3<f32>) -> f32 {
+    var amount = 0.0;
+
+    var i = 0u;
+    loop {
+        if (i >= cuts.count) { break; }
+
+        let cut = cuts.data[i];
+        var inside = false;
+        if (cut.shape == 0u) {
+            inside = length(position - cut.center.xyz) < cut.center.w;
+        } else {
+            let local = quat_rotate(cut.rotation, position - cut.center.xyz);
+            inside = all(abs(local) <= cut.half_extents.xyz);
+        }
+        if (inside) {
+            amount = max(amount, 1.0 - cut.height);
+        }
+
+        continuing { i += 1u; }
+    }
+
+    return amount;
+}
+
+// world space wind at a position
+fn wind_at(position: vec3<f32>) -> vec3<f32> {
+    // gusts are waves rolling along the wind direction
+    let along = dot(position, wind.direction);
+    let gust_wave = sin((along / max(wind.speed, 0.0001) - params.time) * wind.gust_frequency * TAU);
+    let gust = (gust_wave * 0.5 + 0.5) * (gust_wave * 0.5 + 0.5);
+    let strength = wind.strength + gust * wind.gust_amplitude;
+
+    // turbulence scrolls with the wind
+    let scroll = wind.direction.xz * wind.speed * params.time;
+    let uv = (position.xz - scroll) / wind.turbulence_scale;
+    let noise = textureSampleLevel(wind_turbulence, wind_sampler, uv, 0.0).rg * 2.0 - 1.0;
+    let turbulence = vec3<f32>(noise.x, 0.0, noise.y) * wind.turbulence_strength;
+
+    return wind.direction * strength + turbulence;
+}
+
+// PCG hash, integer only so every gpu produces the same layout
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// identity of a root, from the bits of its position and the seed of the field
+fn root_hash(position: vec3<f32>) -> u32 {
+    let bits = bitcast<vec3<u32>>(position);
+    return pcg(bits.x ^ pcg(bits.y ^ pcg(bits.z ^ pcg(params.seed))));
+}
+
+// independent random streams of a root, in [0, 1)
+let STREAM_DENSITY: u32  = 0u;
+let STREAM_LOD: u32      = 1u;
+let STREAM_ROTATION: u32 = 2u;
+let STREAM_HEIGHT: u32   = 3u;
+let STREAM_WIDTH: u32    = 4u;
+let STREAM_HUE: u32      = 5u;
+let STREAM_VALUE: u32    = 6u;
+let STREAM_SPECIES: u32  = 7u;
+
+fn random(root: u32, stream: u32, index: u32) -> f32 {
+    let hash = pcg(root ^ pcg(stream * 65536u + index));
+    return f32(hash >> 8u) / 16777216.0;
 }
 
 let PI: f32  = 3.14159265358979323846;
 let TAU: f32 = 6.28318530717958647693;
+let RESET_STRIP: u32 = 4294967295u;
 
 @compute @workgroup_size(256)
 fn cs_main_fill(@builtin(global_invocation_id) global_id: vec3<u32>) {
@@ -81,95 +456,236 @@ fn cs_main_fill(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let src_vertex = src_vertices[src_index];
     let src_position = vec3<f32>(src_vertex[0], src_vertex[1], src_vertex[2]);
     let src_normal   = vec3<f32>(src_vertex[3], src_vertex[4], src_vertex[5]);
+    let src_uv       = vec2<f32>(src_vertex[6], src_vertex[7]);
 
-    let segments_per_blade = 5u;
-    let top_vtx_offset = segments_per_blade * 2u;
-    let vtx_per_blade  = segments_per_blade * 2u + 1u;
-    let idx_per_blade  = segments_per_blade * 2u + 2u;
+    let root = root_hash(src_position);
 
-    let dst_index = atomicAdd(&dst_vertices_count, vtx_per_blade);
+    // thin out the grass where the density map is dark, the survivors get shorter
+    let density = textureSampleLevel(density_map, density_sampler, src_uv, 0.0).r;
+    if (density <= random(root, STREAM_DENSITY, 0u)) {
+        return;
+    }
 
-    let rand_seed = fract(sin(dot(src_position.xyz, vec3<f32>(12.9898, 78.233, 53.539))) * 43758.5453);
+    let world_position = (params.model * vec4<f32>(src_position, 1.0)).xyz;
 
-    let blade_bottom_width = 0.50;
-    let blade_width  = 0.02;
-    let blade_height = 0.50;
+    // Cutting, remembered per root and regrowing over time
 
-    // Wind
+    let stamp = cut_state[src_index];
+    var cut_amount = max(stamp.x - (params.time - stamp.y) * params.regrowth, 0.0);
+    let new_cut = cut_at(world_position);
+    if (new_cut > cut_amount) {
+        cut_state[src_index] = vec2<f32>(new_cut, params.time);
+        cut_amount = new_cut;
+    }
+    if (cut_amount >= 0.99) {
+        return;
+    }
 
-    let wind_speed = params.time * params.wind_speed;
-    let wind_sin =
-        sin(wind_speed + src_position.x      ) +
-        sin(wind_speed + src_position.z * 2.0) + // z
-        sin(wind_speed * 0.1 + src_position.x);
-    let wind_cos =
-        cos(wind_speed + src_position.x * 2.0) +
-        cos(wind_speed + src_position.z      ); // z
+    // Species, weighted by the species map
 
-    //let wind = vec3<f32>(wind_x, 0.0, wind_z) * params_wind_strength;
-    let wind = vec3<f32>(wind_sin, wind_cos, 0.0) * params.wind_strength;
+    let species_density = textureSampleLevel(species_map, species_sampler, src_uv, 0.0);
+    var species_weights: array<f32, 4>;
+    var species_total = 0.0;
+    var species_index = 0u;
+    loop {
+        if (species_index >= params.species_count) { break; }
+        species_weights[species_index] = params.species[species_index].weight * species_density[species_index];
+        species_total += species_weights[species_index];
+        continuing { species_index += 1u; }
+    }
 
-    let rotation_axis = vec3<f32>(0.0, 1.0, -0.1);
-    //let rotation_axis = vec3<f32>(-0.1, 0.0, 1.0);
+    // nothing grows here
+    if (species_total <= 0.0) {
+        return;
+    }
 
-    var displacement = vec3<f32>(0.0) + wind;
+    let height_scale = mix(0.5, 1.0, density) * (1.0 - cut_amount);
+    let blade_height = params.max_height * height_scale;
 
-    var position: array<vec3<f32>, 55u>;
-    var texcoord: array<vec2<f32>, 55u>;
+    let world_scale = max(length(params.model[0].xyz), max(length(params.model[1].xyz), length(params.model[2].xyz)));
 
-    var blade_index = 0u;
-    loop {
-        if (blade_index >= params.blades) { break; }
+    // Interactors
 
-        // set rotation and radius of the blades
+    let model3 = mat3x3<f32>(params.model[0].xyz, params.model[1].xyz, params.model[2].xyz);
+    let world_normal = normalize(model3 * src_normal);
+    // back into the local space of the field, assuming uniform scale
+    var push = (interactor_push(world_position, world_normal) * model3) / (world_scale * world_scale);
 
-        let blade_rotation = angle_axis_3x3(rand_seed * TAU + f32(blade_index), rotation_axis);
-        let blade_radius = f32(blade_index) / f32(params.blades);
-        let blade_offset = (1.0 - blade_radius) * params.blade_radius;
+    // Trampling, updated before culling so trails are left off screen too
 
-        var segment_index = 0u;
-        loop {
-            if (segment_index >= segments_per_blade) { break; }
+    if (params.trampling != 0u) {
+        let stamp = trample[src_index];
+        let recovery = max(1.0 - (params.time - stamp.w) * params.trample_recovery, 0.0);
+        let trampled = stamp.xyz * recovery;
 
-            let taper_width = f32(segment_index) / f32(segments_per_blade);
+        if (length(push) > length(trampled)) {
+            trample[src_index] = vec4<f32>(push, params.time);
+        } else {
+            push = trampled;
+        }
+    }
 
-            // the first segment is thinner
-            let first_thinner = select(1.0, blade_bottom_width, segment_index == 0u);
-            let width = blade_width * (1.0 - taper_width) * first_thinner;
-            let height = blade_height * taper_width;
+    // pushed blades lean over instead of just sliding sideways
+    let push_length = min(length(push), blade_height);
+    let bend = normalize(push + vec3<f32>(0.0001)) * push_length - src_normal * push_length * 0.5;
 
-            let forward = blade_offset + pow(abs(taper_width), params.blade_curve) * params.blade_forward;
+    // the bounding sphere of all blades of the root, scaled into world space
+    let radius = (blade_height + params.blade_radius + params.max_forward) * world_scale;
+    let visible = (params.frustum_culling == 0u || is_visible(world_position, radius))
+        && (params.occlusion_culling == 0u || !is_occluded(world_position, radius));
+    // the blades are shared with the shadow maps
+    if (!visible && (params.cast_shadows == 0u || !casts_shadow(world_position, radius))) {
+        return;
+    }
 
-            // first grass (0) segment does not get displaced by interactor
-            let translation = src_position + select(displacement * taper_width, vec3<f32>(0.0), segment_index == 0u);
+    // Level of detail
 
-            let offset = segment_index * 2u;
+    var segments_per_blade = params.segments;
+    var blades = params.blades;
+    // blades missing from the next band thin out over the last `lod_fade` units of this one
+    var kept_blades = params.blades;
+    var fade = 0.0;
 
-            position[offset + 0u] = translation + vec3<f32>( width, height, forward) * blade_rotation;
-            position[offset + 1u] = translation + vec3<f32>(-width, height, forward) * blade_rotation;
+    if (params.lod_count != 0u) {
+        let distance = view_distance(world_position);
 
-            texcoord[offset + 0u] = vec2<f32>(0.0, taper_width);
-            texcoord[offset + 1u] = vec2<f32>(1.0, taper_width);
+        var band = 0u;
+        loop {
+            if (band >= params.lod_count || distance < params.lod_bands[band].distance) { break; }
+            continuing { band += 1u; }
+        }
+
+        // beyond the last band
+        if (band >= params.lod_count) {
+            return;
+        }
 
-            continuing { segment_index += 1u; }
+        let lod = params.lod_bands[band];
+        segments_per_blade = clamp(lod.segments, 1u, params.segments);
+        blades = min(lod.blades, params.blades);
+        kept_blades = 0u;
+        if (band + 1u < params.lod_count) {
+            kept_blades = min(params.lod_bands[band + 1u].blades, blades);
         }
+        fade = smoothstep(lod.distance - max(params.lod_fade, 0.0001), lod.distance, distance);
+    }
 
-        // top vertex
-        let translation = src_position + displacement;
-        let forward = blade_offset + params.blade_forward;
-        let local_displacement = vec3<f32>(0.0, blade_height, forward);
-        position[top_vtx_offset] = translation + local_displacement * blade_rotation;
-        texcoord[top_vtx_offset] = vec2<f32>(0.5, 1.0);
+    // Distance fade
 
-        continuing { blade_index += 1u; }
+    var opacity = 1.0;
+    if (params.fade != 0u) {
+        opacity = 1.0 - smoothstep(params.fade_start, params.fade_end, view_distance(world_position));
+        if (opacity <= 0.0) {
+            return;
+        }
     }
 
-    var i = 0u;
+    let vtx_per_blade  = segments_per_blade * 2u + 1u;
+    let idx_per_blade  = segments_per_blade * 2u + 2u;
+
+    // Wind, back into the local space of the field like the interactors
+
+    let wind = (wind_at(world_position) * model3) / (world_scale * world_scale) * params.wind_strength;
+
+    var displacement = vec3<f32>(0.0) + wind + bend;
+
+    // dry and lush patches
+    let tint = textureSampleLevel(color_map, color_sampler, world_position.xz / params.color_map_scale, 0.0).rgb;
+
+    var blade_index = 0u;
     loop {
-        if (i >= vtx_per_blade) { break; }
-        set_vertex(dst_index + i, src_normal, position[i], texcoord[i]);
-        continuing { i += 1u; }
-    }
+        if (blade_index >= blades) { break; }
+
+        // drop the blades of this root one by one while fading into the next band
+        if (blade_index >= kept_blades && random(root, STREAM_LOD, blade_index) < fade) {
+            continue;
+        }
+
+        // set rotation and radius of the blades
 
-    atomicAdd(&dst_indirect.vertex_count, idx_per_blade);
-}
\ No newline at end of file
+        let blade_angle = random(root, STREAM_ROTATION, blade_index) * TAU;
+        let blade_radius = f32(blade_index) / f32(params.blades);
+        let blade_offset = (1.0 - blade_radius) * params.blade_radius;
+
+        // species of the blade
+        var pick = random(root, STREAM_SPECIES, blade_index) * species_total;
+        var kind = 0u;
+        loop {
+            if (kind + 1u >= params.species_count || pick < species_weights[kind]) { break; }
+            pick -= species_weights[kind];
+            continuing { kind += 1u; }
+        }
+        let species = params.species[kind];
+
+        // shape and color jitter of the blade
+        let scaled_height = mix(species.height_min, species.height_max, random(root, STREAM_HEIGHT, blade_index)) * height_scale;
+        let scaled_width = mix(species.width_min, species.width_max, random(root, STREAM_WIDTH, blade_index));
+
+        let hue = (random(root, STREAM_HUE, blade_index) * 2.0 - 1.0) * params.hue_variation;
+        let value = random(root, STREAM_VALUE, blade_index) * 2.0 - 1.0;
+        let shade = max(1.0 + value * params.value_variation, 0.0) * tint;
+        let base_color = hue_shift(species.base_color.rgb, hue) * shade;
+        let tip_color = hue_shift(species.tip_color.rgb, hue) * shade;
+
+        let blade = BladeInstance(
+            vec4<f32>(src_position, scaled_height),
+            vec4<f32>(src_normal, scaled_width),
+            vec4<f32>(displacement, species.forward),
+            vec4<f32>(blade_angle, species.curve, species.bottom_width, blade_offset),
+            vec4<u32>(pack_color(base_color, 1.0), pack_color(tip_color, 1.0), segments_per_blade, bitcast<u32>(opacity)),
+        );
+
+        let blade_slot = atomicAdd(&dst_counters.blades, 1u);
+
+        // only the reservations that fit are drawn, and those are always the first ones
+        if (params.instanced != 0u) {
+            if (blade_slot >= params.capacity_blades) {
+                atomicAdd(&dst_counters.dropped, 1u);
+                continue;
+            }
+            dst_instances[blade_slot] = blade;
+            atomicAdd(&dst_indirect.instance_count, 1u);
+            continue;
+        }
+
+        let dst_index = atomicAdd(&dst_counters.vertices, vtx_per_blade);
+        let idx_index = atomicAdd(&dst_counters.indices, idx_per_blade);
+
+        if (idx_index + idx_per_blade > params.capacity_indices) {
+            atomicAdd(&dst_counters.dropped, 1u);
+            continue;
+        }
+
+        // the indices fit but the vertices do not, the reserved strip draws nothing
+        if (dst_index + vtx_per_blade > params.capacity_vertices) {
+            var i = 0u;
+            loop {
+                if (i >= idx_per_blade) { break; }
+                dst_indices[idx_index + i] = RESET_STRIP;
+                continuing { i += 1u; }
+            }
+            atomicAdd(&dst_indirect.vertex_count, idx_per_blade);
+            atomicAdd(&dst_counters.dropped, 1u);
+            continue;
+        }
+
+        var v = 0u;
+        loop {
+            if (v >= vtx_per_blade) { break; }
+            set_vertex(dst_index + v, blade_vertex(blade, v));
+            continuing { v += 1u; }
+        }
+
+        // triangle strip of the blade
+        var i = 0u;
+        loop {
+            if (i >= vtx_per_blade) { break; }
+            dst_indices[idx_index + i] = dst_index + i;
+            continuing { i += 1u; }
+        }
+        dst_indices[idx_index + vtx_per_blade] = RESET_STRIP;
+        atomicAdd(&dst_indirect.vertex_count, idx_per_blade);
+
+        continuing { blade_index += 1u; }
+    }
+}
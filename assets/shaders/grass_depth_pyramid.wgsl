@@ -0,0 +1,75 @@
+// depth pyramid for the grass occlusion culling, every texel keeps the farthest depth below it
+// (reverse z, so the smallest value)
+
+#ifndef DOWNSAMPLE
+#ifdef MULTISAMPLED
+@group(0) @binding(0) var depth: texture_depth_multisampled_2d;
+#endif
+#ifndef MULTISAMPLED
+@group(0) @binding(0) var depth: texture_depth_2d;
+#endif
+#endif
+
+#ifdef DOWNSAMPLE
+@group(0) @binding(0) var src: texture_2d<f32>;
+#endif
+
+@group(0) @binding(1) var dst: texture_storage_2d<r32float, write>;
+
+#ifndef DOWNSAMPLE
+@compute @workgroup_size(8, 8, 1)
+fn copy(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    if (any(coords >= textureDimensions(dst))) {
+        return;
+    }
+
+#ifdef MULTISAMPLED
+    var farthest = 1.0;
+    var sample_index = 0;
+    loop {
+        if (sample_index >= textureNumSamples(depth)) { break; }
+        farthest = min(farthest, textureLoad(depth, coords, sample_index));
+        continuing { sample_index += 1; }
+    }
+#endif
+#ifndef MULTISAMPLED
+    let farthest = textureLoad(depth, coords, 0);
+#endif
+
+    textureStore(dst, coords, vec4<f32>(farthest, 0.0, 0.0, 0.0));
+}
+#endif
+
+#ifdef DOWNSAMPLE
+@compute @workgroup_size(8, 8, 1)
+fn downsample(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    let size = textureDimensions(dst);
+    if (any(coords >= size)) {
+        return;
+    }
+
+    // the last texel of an odd sized source also covers its last row or column
+    let src_size = textureDimensions(src);
+    let odd = (src_size & vec2<i32>(1)) == vec2<i32>(1);
+    let last = coords == size - 1;
+    let extent = select(vec2<i32>(1), vec2<i32>(2), odd & last);
+
+    var farthest = 1.0;
+    var y = 0;
+    loop {
+        if (y > extent.y) { break; }
+        var x = 0;
+        loop {
+            if (x > extent.x) { break; }
+            let texel = min(coords * 2 + vec2<i32>(x, y), src_size - 1);
+            farthest = min(farthest, textureLoad(src, texel, 0).r);
+            continuing { x += 1; }
+        }
+        continuing { y += 1; }
+    }
+
+    textureStore(dst, coords, vec4<f32>(farthest, 0.0, 0.0, 0.0));
+}
+#endif
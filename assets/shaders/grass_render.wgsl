@@ -4,15 +4,72 @@
 @group(1) @binding(0)
 var<uniform> mesh: Mesh;
 
+struct GrassMaterial {
+    translucency_color: vec4<f32>,
+
+    wrap: f32,
+    ambient: f32,
+    translucency: f32,
+    translucency_power: f32,
+
+    translucency_distortion: f32,
+    specular: f32,
+    shininess: f32,
+    toon_bands: u32,
+
+    toon_softness: f32,
+    toon_ramp: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> material: GrassMaterial;
+@group(2) @binding(1)
+var toon_ramp: texture_2d<f32>;
+@group(2) @binding(2)
+var toon_ramp_sampler: sampler;
+
 // NOTE: Bindings must come before functions that use them!
 #import bevy_pbr::mesh_functions
 
+#import grass::blade
+
+#ifdef INSTANCED
+struct Vertex {
+    @builtin(vertex_index) index: u32,
+    @location(0) position_height: vec4<f32>,
+    @location(1) normal_width: vec4<f32>,
+    @location(2) displacement_forward: vec4<f32>,
+    @location(3) shape: vec4<f32>,
+    @location(4) data: vec4<u32>,
+};
+
+fn load_vertex(vertex: Vertex) -> BladeVertex {
+    let blade = BladeInstance(
+        vertex.position_height,
+        vertex.normal_width,
+        vertex.displacement_forward,
+        vertex.shape,
+        vertex.data,
+    );
+    return blade_vertex(blade, vertex.index);
+}
+#endif
+
+#ifndef INSTANCED
 struct Vertex {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) uv: vec2<f32>,
+    @location(2) face: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+    // square root of the linear color + opacity
+    @location(4) color: vec4<f32>,
 };
 
+fn load_vertex(vertex: Vertex) -> BladeVertex {
+    return BladeVertex(vertex.position, vertex.normal, vertex.face, vertex.uv, vertex.color.rgb * vertex.color.rgb, vertex.color.a);
+}
+#endif
+
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 
@@ -20,16 +77,20 @@ struct VertexOutput {
     @location(1) world_normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) color: vec4<f32>,
+    @location(4) world_face: vec3<f32>,
 };
 
 @vertex
-fn vertex(vertex: Vertex) -> VertexOutput {
+fn vertex(input: Vertex) -> VertexOutput {
+    let vertex = load_vertex(input);
+
     var out: VertexOutput;
     out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(vertex.position, 1.0));
     out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
     out.world_normal = mesh_normal_local_to_world(vertex.normal);
     out.uv = vertex.uv;
-    out.color = vec4<f32>(vec3<f32>(0.079, 0.245, 0.160)*vertex.uv.y, 1.0);
+    out.color = vec4<f32>(vertex.color, vertex.opacity);
+    out.world_face = mesh_normal_local_to_world(vertex.face);
     return out;
 }
 
@@ -52,60 +113,221 @@ struct FragInput {
     @location(1) world_normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) color: vec4<f32>,
+    @location(4) world_face: vec3<f32>,
 }
 
-@fragment
-fn fragment(in: FragInput) -> @location(0) vec4<f32> {
-    //return vec4<f32>(0.5, 0.5, 0.5, 1.0);
+// per pixel threshold for the dissolve without msaa, by Jorge Jimenez
+fn interleaved_gradient_noise(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// soft edges across the blade, about a pixel wide, times the distance fade
+fn blade_alpha(uv: vec2<f32>, opacity: f32) -> f32 {
+    let edge = 1.0 - abs(uv.x * 2.0 - 1.0);
+    return opacity * clamp(edge / max(fwidth(edge), 0.0001), 0.0, 1.0);
+}
 
-    // Prepare a 'processed' StandardMaterial by sampling all textures to resolve
-    // the material members
-    var pbr_input: PbrInput = pbr_input_new();
+struct GrassSurface {
+    color: vec3<f32>,
+    // normal of the ground the blade grows from
+    N: vec3<f32>,
+    // normal of the bent blade itself, facing the viewer
+    F: vec3<f32>,
+    V: vec3<f32>,
+    // fraction of the light passing through, the tips are thinner
+    transmission: f32,
+};
 
-    //pbr_input.material.base_color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
-    pbr_input.material.base_color = in.color;
+#ifdef TOON
+// flat steps of light, blended over `toon_softness` of a band, or the color of the ramp
+fn toon_shade(intensity: f32) -> vec3<f32> {
+    let x = saturate(intensity);
+    if (material.toon_ramp != 0u) {
+        // the lights are looped over, no derivatives here
+        return textureSampleLevel(toon_ramp, toon_ramp_sampler, vec2<f32>(x, 0.5), 0.0).rgb;
+    }
 
-#ifdef VERTEX_COLORS
-    pbr_input.material.base_color = pbr_input.material.base_color * in.color;
+    // steps halfway between the levels, a single band splits into lit and unlit
+    let bands = f32(material.toon_bands);
+    let scaled = x * bands;
+    let softness = max(material.toon_softness, 0.0001) * 0.5;
+    let blend = smoothstep(0.5 - softness, 0.5 + softness, fract(scaled));
+    return vec3<f32>((floor(scaled) + blend) / bands);
+}
 #endif
 
-    //pbr_input.material.reflectance = 0.1;
-    pbr_input.material.perceptual_roughness = 1.0;
+// light reaching the eye from a light arriving along `L`,
+// `radiance` is the color of the light and `visibility` its attenuation and shadow
+fn grass_light(surface: GrassSurface, L: vec3<f32>, radiance: vec3<f32>, visibility: f32) -> vec3<f32> {
+    // wrapped lambert, the blades are lit from all around
+    let wrap = material.wrap;
+    let NdotL_wrap = max((dot(surface.N, L) + wrap) / (1.0 + wrap), 0.0);
 
+    // light passing through the blade towards the viewer
+    let H_t = normalize(L + surface.N * material.translucency_distortion);
+    let VdotH_t = saturate(dot(surface.V, -H_t));
+    let transmitted = pow(VdotH_t, material.translucency_power) * surface.transmission;
 
-    pbr_input.frag_coord = in.frag_coord;
-    pbr_input.world_position = in.world_position;
-    pbr_input.world_normal = in.world_normal;
+    // blinn phong on the blade face, moving with the wind
+    let H = normalize(L + surface.V);
+    let NdotH = saturate(dot(surface.F, H));
+    let highlight = pow(NdotH, material.shininess) * saturate(dot(surface.F, L));
 
-    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+#ifdef TOON
+    let diffuse = toon_shade(NdotL_wrap * visibility) * surface.color / PI;
+    let translucency = toon_shade(transmitted * visibility) * material.translucency
+        * material.translucency_color.rgb / PI;
+    let specular = step(0.5, highlight * visibility) * material.specular;
+#endif
+#ifndef TOON
+    let diffuse = NdotL_wrap * visibility * surface.color / PI;
+    let translucency = transmitted * visibility * material.translucency
+        * material.translucency_color.rgb / PI;
+    let specular = highlight * visibility * material.specular;
+#endif
 
-    pbr_input.N = prepare_normal(
-        pbr_input.material.flags,
-        in.world_normal,
-        in.uv,
-        in.is_front,
-    );
+    return (diffuse + translucency + vec3<f32>(specular)) * radiance;
+}
+
+fn grass_lighting(in: FragInput, surface: GrassSurface, is_orthographic: bool) -> vec3<f32> {
+    var light_accum = vec3<f32>(0.0);
+
+    let view_z = dot(vec4<f32>(
+        view.inverse_view[0].z,
+        view.inverse_view[1].z,
+        view.inverse_view[2].z,
+        view.inverse_view[3].z
+    ), in.world_position);
+    let cluster_index = fragment_cluster_index(in.frag_coord.xy, view_z, is_orthographic);
+    let offset_and_counts = unpack_offset_and_counts(cluster_index);
+
+    for (var i: u32 = offset_and_counts[0]; i < offset_and_counts[0] + offset_and_counts[1]; i = i + 1u) {
+        let light_id = get_light_id(i);
+        let light = point_lights.data[light_id];
+        var shadow: f32 = 1.0;
+        if ((mesh.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u
+                && (light.flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_point_shadow(light_id, in.world_position, in.world_normal);
+        }
+
+        let light_to_frag = light.position_radius.xyz - in.world_position.xyz;
+        let distance_square = dot(light_to_frag, light_to_frag);
+        let attenuation = getDistanceAttenuation(distance_square, light.color_inverse_square_range.w);
+        let L = normalize(light_to_frag);
+        let radiance = light.color_inverse_square_range.rgb;
+        light_accum = light_accum + grass_light(surface, L, radiance, attenuation * shadow);
+    }
 
-    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    let spot_lights_start = offset_and_counts[0] + offset_and_counts[1];
+    for (var i: u32 = spot_lights_start; i < spot_lights_start + offset_and_counts[2]; i = i + 1u) {
+        let light_id = get_light_id(i);
+        let light = point_lights.data[light_id];
+        var shadow: f32 = 1.0;
+        if ((mesh.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u
+                && (light.flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_spot_shadow(light_id, in.world_position, in.world_normal);
+        }
 
-    return tone_mapping(pbr(pbr_input));
+        // the direction of the spot is packed as its x and z, the sign of y is a flag
+        var spot_dir = vec3<f32>(light.light_custom_data.x, 0.0, light.light_custom_data.y);
+        spot_dir.y = sqrt(max(0.0, 1.0 - spot_dir.x * spot_dir.x - spot_dir.z * spot_dir.z));
+        if ((light.flags & POINT_LIGHT_FLAGS_SPOT_LIGHT_Y_NEGATIVE) != 0u) {
+            spot_dir.y = -spot_dir.y;
+        }
+
+        let light_to_frag = light.position_radius.xyz - in.world_position.xyz;
+        let distance_square = dot(light_to_frag, light_to_frag);
+        let L = normalize(light_to_frag);
+        let cone = saturate(dot(-spot_dir, L) * light.light_custom_data.z + light.light_custom_data.w);
+        let attenuation = getDistanceAttenuation(distance_square, light.color_inverse_square_range.w)
+            * cone * cone;
+        let radiance = light.color_inverse_square_range.rgb;
+        light_accum = light_accum + grass_light(surface, L, radiance, attenuation * shadow);
+    }
+
+    let n_directional_lights = lights.n_directional_lights;
+    for (var i: u32 = 0u; i < n_directional_lights; i = i + 1u) {
+        let light = lights.directional_lights[i];
+        var shadow: f32 = 1.0;
+        if ((mesh.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u
+                && (light.flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_directional_shadow(i, in.world_position, in.world_normal);
+        }
+
+        let L = light.direction_to_light;
+        light_accum = light_accum + grass_light(surface, L, light.color.rgb, shadow);
+    }
+
+    let ambient = lights.ambient_color.rgb * surface.color * material.ambient;
+    return light_accum + ambient;
 }
 
+@fragment
+fn fragment(in: FragInput) -> @location(0) vec4<f32> {
+#ifdef FADE
+    let alpha = blade_alpha(in.uv, in.color.a);
+#ifndef ALPHA_TO_COVERAGE
+    if (alpha < interleaved_gradient_noise(in.frag_coord.xy)) {
+        discard;
+    }
+#endif
+#endif
+
+    let is_orthographic = view.projection[3].w == 1.0;
+
+    var surface: GrassSurface;
+    surface.color = in.color.rgb;
+    surface.N = normalize(in.world_normal);
+    surface.V = calculate_view(in.world_position, is_orthographic);
+    let face_normal = normalize(in.world_face);
+    surface.F = select(-face_normal, face_normal, dot(face_normal, surface.V) >= 0.0);
+    surface.transmission = mix(0.5, 1.0, in.uv.y);
+
+    var color = tone_mapping(vec4<f32>(grass_lighting(in, surface, is_orthographic), 1.0));
+#ifdef FADE
+    // turned into coverage under msaa
+    color.a = alpha;
+#endif
+    return color;
+}
 
 struct VertexNormalOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) opacity: f32,
 };
 
 @vertex
-fn vertex_normal_pass(vertex: Vertex) -> VertexNormalOutput {
+fn vertex_normal_pass(input: Vertex) -> VertexNormalOutput {
+    let vertex = load_vertex(input);
     return VertexNormalOutput(
         mesh_position_local_to_clip(mesh.model, vec4<f32>(vertex.position, 1.0)),
-        mesh_normal_local_to_world(vertex.normal)
+        mesh_normal_local_to_world(vertex.normal),
+        vertex.uv,
+        vertex.opacity,
     );
 }
 
+struct FragNormalInput {
+    @builtin(position) frag_coord: vec4<f32>,
+    @location(0) world_normal: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) opacity: f32,
+};
+
 @fragment
-fn fragment_normal_pass(@location(0) world_normal: vec3<f32>) -> @location(0) vec4<f32> {
-    return vec4<f32>(world_normal * vec3<f32>(0.5) + vec3<f32>(0.5), 1.0);
+fn fragment_normal_pass(in: FragNormalInput) -> @location(0) vec4<f32> {
+    var color = vec4<f32>(in.world_normal * vec3<f32>(0.5) + vec3<f32>(0.5), 1.0);
+#ifdef FADE
+    // the same blades dissolve as in the main pass
+    let alpha = blade_alpha(in.uv, in.opacity);
+#ifndef ALPHA_TO_COVERAGE
+    if (alpha < interleaved_gradient_noise(in.frag_coord.xy)) {
+        discard;
+    }
+#endif
+    color.a = alpha;
+#endif
+    return color;
 }
\ No newline at end of file
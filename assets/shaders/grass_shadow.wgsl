@@ -0,0 +1,62 @@
+#import bevy_pbr::mesh_view_types
+#import bevy_pbr::mesh_types
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var<uniform> mesh: Mesh;
+
+// NOTE: Bindings must come before functions that use them!
+#import bevy_pbr::mesh_functions
+
+#import grass::blade
+
+#ifdef INSTANCED
+struct Vertex {
+    @builtin(vertex_index) index: u32,
+    @location(0) position_height: vec4<f32>,
+    @location(1) normal_width: vec4<f32>,
+    @location(2) displacement_forward: vec4<f32>,
+    @location(3) shape: vec4<f32>,
+    @location(4) data: vec4<u32>,
+};
+
+fn vertex_position(vertex: Vertex) -> vec3<f32> {
+    let blade = BladeInstance(
+        vertex.position_height,
+        vertex.normal_width,
+        vertex.displacement_forward,
+        vertex.shape,
+        vertex.data,
+    );
+    return blade_vertex(blade, vertex.index).position;
+}
+#endif
+
+#ifndef INSTANCED
+// only the positions of the blade vertices are read
+struct Vertex {
+    @location(0) position: vec3<f32>,
+};
+
+fn vertex_position(vertex: Vertex) -> vec3<f32> {
+    return vertex.position;
+}
+#endif
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(vertex_position(vertex), 1.0));
+    // the orthographic views of directional lights only cover the cameras,
+    // blades in front of their near plane are clamped onto it instead of being clipped
+    if (view.projection[3].w == 1.0) {
+        out.clip_position.z = min(out.clip_position.z, 1.0);
+    }
+    return out;
+}
@@ -0,0 +1,142 @@
+use bevy::app::AppExit;
+use bevy::diagnostic::Diagnostics;
+use bevy::prelude::*;
+
+use crate::toon::grass::{GrassBundle, GrassConfig, GrassSurface};
+use crate::toon::GrassDiagnosticsPlugin;
+
+/// Fields along each side of the benchmark grid.
+const FIELDS: i32 = 4;
+const FIELD_SIZE: f32 = 16.0;
+/// 12.8k roots and 64k blades per field, about 1M blades over the grid.
+const DENSITY: f32 = 50.0;
+
+const WARMUP_FRAMES: usize = 120;
+const MEASURED_FRAMES: usize = 600;
+
+/// Renders a large grid of grass fields at full detail from a fixed camera,
+/// first in the vertex mode and then in the instanced mode, and logs the frame times of both.
+///
+/// Run with `cargo run --release -- --bench`. Vsync is off and nothing but the grass is drawn,
+/// so the frame times follow the gpu time of the grass compute and render passes.
+pub fn run() {
+    let mut app = App::new();
+
+    app.insert_resource(Msaa { samples: 4 });
+    app.insert_resource(WindowDescriptor {
+        width: 1920.0,
+        height: 1080.0,
+        present_mode: bevy::window::PresentMode::AutoNoVsync,
+        ..default()
+    });
+
+    app.add_plugins_with(DefaultPlugins, |group| {
+        crate::toon::replace_core_pipeline(group)
+    })
+    .add_plugin(crate::toon::GrassPlugin)
+    .add_plugin(crate::toon::GrassDiagnosticsPlugin)
+    .init_resource::<GrassBench>()
+    .add_startup_system(setup_bench)
+    .add_system(measure);
+
+    app.run();
+}
+
+#[derive(Default)]
+struct GrassBench {
+    instanced: bool,
+    frames: usize,
+    times: Vec<f64>,
+}
+
+fn setup_bench(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    let mesh = meshes.add(Mesh::from(shape::Plane { size: FIELD_SIZE }));
+
+    // every root at full detail and drawn, so both modes do the same work
+    let config = GrassConfig {
+        lod_bands: Vec::new(),
+        frustum_culling: false,
+        occlusion_culling: false,
+        cast_shadows: false,
+        ..default()
+    };
+
+    let offset = (FIELDS - 1) as f32 * FIELD_SIZE * 0.5;
+    for z in 0..FIELDS {
+        for x in 0..FIELDS {
+            commands.spawn_bundle(GrassBundle {
+                surface: GrassSurface {
+                    mesh: mesh.clone(),
+                    density: DENSITY,
+                },
+                config: config.clone(),
+                transform: Transform::from_xyz(
+                    x as f32 * FIELD_SIZE - offset,
+                    0.0,
+                    z as f32 * FIELD_SIZE - offset,
+                ),
+                ..default()
+            });
+        }
+    }
+
+    commands.spawn_bundle(Camera3dBundle {
+        transform: Transform::from_xyz(0.0, 12.0, 40.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+
+    commands.spawn_bundle(DirectionalLightBundle {
+        transform: Transform::from_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_4)),
+        ..default()
+    });
+}
+
+fn measure(
+    time: Res<Time>,
+    diagnostics: Res<Diagnostics>,
+    mut bench: ResMut<GrassBench>,
+    mut configs: Query<&mut GrassConfig>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // the fields are scattered and allocated during the first frames
+    bench.frames += 1;
+    if bench.frames <= WARMUP_FRAMES {
+        return;
+    }
+
+    bench.times.push(time.delta_seconds_f64() * 1000.0);
+    if bench.times.len() < MEASURED_FRAMES {
+        return;
+    }
+
+    let mut times = std::mem::take(&mut bench.times);
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let median = times[times.len() / 2];
+    let p99 = times[times.len() * 99 / 100];
+    let blades = diagnostics
+        .get(GrassDiagnosticsPlugin::BLADES)
+        .and_then(|diagnostic| diagnostic.value())
+        .unwrap_or(0.0);
+
+    info!(
+        "{} mode: {:.0} blades, mean {:.2} ms, median {:.2} ms, 99th percentile {:.2} ms over {} frames",
+        if bench.instanced { "instanced" } else { "vertex" },
+        blades,
+        mean,
+        median,
+        p99,
+        times.len(),
+    );
+
+    if bench.instanced {
+        exit.send(AppExit);
+        return;
+    }
+
+    bench.instanced = true;
+    bench.frames = 0;
+    for mut config in &mut configs {
+        config.instanced = true;
+    }
+}
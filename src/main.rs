@@ -3,10 +3,16 @@
 
 use bevy::prelude::*;
 
+mod bench;
 mod camera;
 mod toon;
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--bench") {
+        crate::bench::run();
+        return;
+    }
+
     let mut app = App::new();
 
     app.add_system(app_exit);
@@ -23,6 +29,8 @@ fn main() {
     })
     .add_startup_system(setup_scene)
     .add_system(movement)
+    .add_system(mow)
+    .add_system(toggle_instanced_grass)
     .add_system(animate_light_direction);
 
     app.add_startup_system(crate::camera::spawn_camera)
@@ -31,6 +39,12 @@ fn main() {
     {
         app.add_plugin(crate::toon::GrassPlugin); // mostly working
 
+        // `cargo run -- --diagnostics` logs the grass counters every second
+        if std::env::args().any(|arg| arg == "--diagnostics") {
+            app.add_plugin(crate::toon::GrassDiagnosticsPlugin);
+            app.add_plugin(bevy::diagnostic::LogDiagnosticsPlugin::default());
+        }
+
         app.add_plugin(crate::toon::NormalPassPlugin); // working, but useless now
         app.add_plugin(crate::toon::PostprocessPassPlugin);
         app.add_plugin(crate::toon::OutlinePlugin); // not working
@@ -48,7 +62,39 @@ fn setup_scene(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    commands.spawn_bundle(crate::toon::grass::GrassBundle::default());
+    commands.spawn_bundle(crate::toon::grass::GrassBundle {
+        surface: crate::toon::grass::GrassSurface {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 5.0 })),
+            ..default()
+        },
+        ..default()
+    });
+    commands.spawn_bundle(crate::toon::grass::GrassBundle {
+        surface: crate::toon::grass::GrassSurface {
+            mesh: meshes.add(Mesh::from(shape::Plane { size: 2.0 })),
+            ..default()
+        },
+        // lawn mixed with a few reeds
+        config: crate::toon::grass::GrassConfig {
+            species: vec![
+                crate::toon::grass::GrassSpecies::default(),
+                crate::toon::grass::GrassSpecies {
+                    height_min: 0.9,
+                    height_max: 1.3,
+                    width_min: 0.01,
+                    width_max: 0.015,
+                    forward: 0.15,
+                    base_color: Color::rgb_linear(0.03, 0.03, 0.01),
+                    tip_color: Color::rgb_linear(0.2, 0.18, 0.06),
+                    weight: 0.15,
+                    ..default()
+                },
+            ],
+            ..default()
+        },
+        transform: Transform::from_xyz(-3.0, 0.0, 3.0),
+        ..default()
+    });
 
     // ground plane
     commands
@@ -107,6 +153,10 @@ fn setup_scene(
             ..default()
         })
         .insert(Movable)
+        .insert(crate::toon::grass::GrassInteractor {
+            radius: 0.9,
+            ..default()
+        })
         .insert(crate::toon::normal_pass::NormalPassMaterial);
 
     // sphere
@@ -124,6 +174,10 @@ fn setup_scene(
             ..default()
         })
         .insert(Movable)
+        .insert(crate::toon::grass::GrassInteractor {
+            radius: 0.6,
+            ..default()
+        })
         .insert(crate::toon::normal_pass::NormalPassMaterial);
 
     // ambient light
@@ -284,6 +338,37 @@ fn movement(
     }
 }
 
+fn mow(
+    input: Res<Input<KeyCode>>,
+    mut cuts: EventWriter<crate::toon::grass::GrassCut>,
+    query: Query<&GlobalTransform, With<Movable>>,
+) {
+    if input.pressed(KeyCode::Space) {
+        for transform in &query {
+            cuts.send(crate::toon::grass::GrassCut {
+                shape: crate::toon::grass::GrassCutShape::Sphere {
+                    center: transform.translation(),
+                    radius: 1.0,
+                },
+                height: 0.2,
+            });
+        }
+    }
+}
+
+/// Switches every field between the vertex and the instanced mode, to compare the two.
+fn toggle_instanced_grass(
+    input: Res<Input<KeyCode>>,
+    mut query: Query<&mut crate::toon::grass::GrassConfig>,
+) {
+    if input.just_pressed(KeyCode::I) {
+        for mut config in &mut query {
+            config.instanced = !config.instanced;
+            info!("instanced grass: {}", config.instanced);
+        }
+    }
+}
+
 fn app_exit(input: Res<Input<KeyCode>>) {
     if input.pressed(KeyCode::Escape) {
         std::process::exit(0);
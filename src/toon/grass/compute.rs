@@ -9,10 +9,16 @@ use bevy::{
 use std::borrow::Cow;
 use std::mem::size_of;
 
-use super::{DrawIndexedIndirect, GrassBindGroup, GrassUniform};
+use super::view::GrassViewsUniform;
+use super::wind::WindUniform;
+use super::{DrawIndexedIndirect, GrassBindGroup, GrassBindGroups, GrassUniform};
 
 pub struct GrassComputePipeline {
     pub compute_bind_group_layout: BindGroupLayout,
+    /// Wraps the maps sampled in world space, so they tile.
+    pub repeat_sampler: Sampler,
+    /// Imported by the grass shaders, kept loaded for as long as the pipelines exist.
+    pub blade_shader: Handle<Shader>,
     pub init_pipeline: CachedComputePipelineId,
     pub fill_pipeline: CachedComputePipelineId,
 }
@@ -22,10 +28,20 @@ impl FromWorld for GrassComputePipeline {
         let device = world.resource::<RenderDevice>();
 
         let compute_bind_group_layout = device.create_bind_group_layout(&COMPUTE_LAYOUT);
+        let repeat_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("grass_repeat_sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..default()
+        });
 
-        let compute_shader = world
-            .resource::<AssetServer>()
-            .load("shaders/grass_compute.wgsl");
+        let asset_server = world.resource::<AssetServer>();
+        let compute_shader = asset_server.load("shaders/grass_compute.wgsl");
+        let blade_shader = asset_server.load("shaders/grass_blade.wgsl");
 
         let mut pipeline_cache = world.resource_mut::<PipelineCache>();
         let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
@@ -45,6 +61,8 @@ impl FromWorld for GrassComputePipeline {
 
         Self {
             compute_bind_group_layout,
+            repeat_sampler,
+            blade_shader,
             init_pipeline,
             fill_pipeline,
         }
@@ -61,7 +79,7 @@ impl render_graph::Node for GrassComputeNode {
         context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
-        let GrassBindGroup { bind_group, count } = &world.resource::<GrassBindGroup>();
+        let bind_groups = &world.resource::<GrassBindGroups>().0;
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<GrassComputePipeline>();
 
@@ -74,12 +92,14 @@ impl render_graph::Node for GrassComputeNode {
 
         let mut pass = context.command_encoder.begin_compute_pass(&default());
 
-        pass.set_bind_group(0, bind_group, &[]);
+        for GrassBindGroup { bind_group, count } in bind_groups {
+            pass.set_bind_group(0, bind_group, &[]);
 
-        pass.set_pipeline(init_pipeline);
-        pass.dispatch_workgroups(1, 1, 1);
-        pass.set_pipeline(fill_pipeline);
-        pass.dispatch_workgroups(*count, 1, 1);
+            pass.set_pipeline(init_pipeline);
+            pass.dispatch_workgroups(1, 1, 1);
+            pass.set_pipeline(fill_pipeline);
+            pass.dispatch_workgroups(*count, 1, 1);
+        }
 
         Ok(())
     }
@@ -119,7 +139,7 @@ const COMPUTE_LAYOUT: wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLayoutDes
             count: None,
         },
         wgpu::BindGroupLayoutEntry {
-            binding: 3, // dst_vertices
+            binding: 3, // dst_counters
             visibility: wgpu::ShaderStages::COMPUTE,
             ty: wgpu::BindingType::Buffer {
                 ty: wgpu::BufferBindingType::Storage { read_only: false },
@@ -138,5 +158,159 @@ const COMPUTE_LAYOUT: wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLayoutDes
             },
             count: None,
         },
+        wgpu::BindGroupLayoutEntry {
+            binding: 5, // density_map
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 6, // density_sampler
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 7, // views
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(size_of::<GrassViewsUniform>() as u64),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 8, // dst_indices
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 9, // interactors
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 10, // trample
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 11, // wind
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(size_of::<WindUniform>() as u64),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 12, // wind_turbulence
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 13, // wind_sampler
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 14, // color_map
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 15, // color_sampler
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 16, // cuts
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 17, // cut_state
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 18, // species_map
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 19, // species_sampler
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 20, // dst_instances
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 21, // depth_pyramid
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
     ],
 };
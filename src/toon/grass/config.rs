@@ -0,0 +1,420 @@
+use bevy::prelude::*;
+use std::fmt;
+
+pub const MAX_SEGMENTS: u32 = 32;
+pub const MAX_BLADES: u32 = 32;
+pub const MAX_LOD_BANDS: usize = 4;
+pub const MAX_SPECIES: usize = 4;
+
+/// Per-field grass settings.
+///
+/// Changes are picked up by the render world on the next frame.
+/// Changing `segments` or `blades` reallocates the output buffers of the field.
+#[derive(Clone, Component)]
+pub struct GrassConfig {
+    /// Segments of the blades closest to the camera.
+    pub segments: u32,
+    /// Blades per root closest to the camera.
+    pub blades: u32,
+    pub blade_radius: f32,
+
+    /// Blade shapes growing in this field, every blade picks one by weight.
+    /// Holds the lean and colors that used to be `blade_forward`, `blade_curve`,
+    /// `base_color` and `tip_color` of the config.
+    pub species: Vec<GrassSpecies>,
+    /// Sampled with the surface uvs, channel `i` scales the weight of species `i`.
+    pub species_map: Option<Handle<Image>>,
+
+    /// Reshuffles the roots and every random choice of the blades.
+    pub seed: u32,
+
+    /// How strongly the blades respond to the global `Wind`.
+    pub wind_strength: f32,
+
+    /// Mask sampled with the surface uvs: black culls the grass, white keeps it at full height.
+    pub density_map: Option<Handle<Image>>,
+
+    /// Write one compact record per blade and build the blades in the vertex shader,
+    /// instead of writing every vertex of every blade from the compute pass.
+    pub instanced: bool,
+    /// Read the counters of the compute pass back, a few frames late, to warn about blades
+    /// dropped by full output buffers and grow the buffers to fit them.
+    /// The buffers fit every blade at full detail, so this only catches misconfigurations.
+    pub grow_buffers: bool,
+
+    /// Skip roots outside of every camera frustum.
+    /// Roots of fields casting shadows are kept inside the frustums of the shadow casting lights.
+    pub frustum_culling: bool,
+    /// Skip roots hidden behind the depth of the previous frame, off by default.
+    /// Only applies while a single camera is active,
+    /// hidden roots of fields casting shadows are kept inside the frustums of the lights.
+    pub occlusion_culling: bool,
+
+    /// Detail by distance to the closest camera, sorted from near to far.
+    /// Roots beyond the last band are skipped, an empty list draws full detail everywhere.
+    pub lod_bands: Vec<GrassLod>,
+    /// Distance over which the blades dropped by the next band thin out.
+    pub lod_fade: f32,
+
+    /// Dissolve the blades between `fade_start` and `fade_end` from the closest camera
+    /// and soften their edges, through alpha to coverage under MSAA and a dither otherwise.
+    pub fade: bool,
+    pub fade_start: f32,
+    pub fade_end: f32,
+
+    /// Draw the blades into the shadow maps of the lights.
+    /// The blades are shared with the cameras, the culling keeps the roots any shadow casting light sees.
+    pub cast_shadows: bool,
+
+    /// How the blades respond to the lights of the scene, and whether they are toon shaded.
+    pub lighting: GrassLighting,
+
+    /// Interactors leave trails of flattened grass.
+    pub trampling: bool,
+    /// Fraction of a full trample recovered per second, `0.0` keeps trails forever.
+    pub trample_recovery: f32,
+
+    /// Fraction of the full blade height regrown per second after a `GrassCut`,
+    /// `0.0` keeps the grass cut forever.
+    pub regrowth: f32,
+
+    /// Largest per-blade hue shift in radians.
+    pub hue_variation: f32,
+    /// Largest per-blade brightness change, `0.2` picks between 80% and 120%.
+    pub value_variation: f32,
+
+    /// Tint sampled in world space on the xz plane, for dry and lush patches.
+    /// White keeps the blade colors unchanged.
+    pub color_map: Option<Handle<Image>>,
+    /// Size of one color map tile in world units.
+    pub color_map_scale: f32,
+}
+
+/// Shape and color of a kind of blade.
+#[derive(Debug, Clone)]
+pub struct GrassSpecies {
+    /// Blade height range, picked per blade.
+    pub height_min: f32,
+    pub height_max: f32,
+    /// Blade width range, picked per blade.
+    pub width_min: f32,
+    pub width_max: f32,
+    /// Width of the first segment relative to the rest of the blade.
+    pub bottom_width: f32,
+    /// How far the tip leans forward.
+    pub forward: f32,
+    /// Exponent of the lean along the blade, higher values bend closer to the tip.
+    pub curve: f32,
+
+    /// Color at the root of the blades, blended into `tip_color` along the blade.
+    pub base_color: Color,
+    pub tip_color: Color,
+
+    /// Relative chance of a blade being of this species.
+    pub weight: f32,
+}
+
+impl Default for GrassSpecies {
+    fn default() -> Self {
+        Self {
+            height_min: 0.4,
+            height_max: 0.5,
+            width_min: 0.016,
+            width_max: 0.024,
+            bottom_width: 0.5,
+            forward: 0.38,
+            curve: 2.1,
+
+            base_color: Color::rgb_linear(0.012, 0.037, 0.024),
+            tip_color: Color::rgb_linear(0.079, 0.245, 0.160),
+
+            weight: 1.0,
+        }
+    }
+}
+
+/// Response of the blades to the lights of the scene.
+#[derive(Debug, Clone)]
+pub struct GrassLighting {
+    /// How far the diffuse light wraps around the blades, `0.0` is plain lambert.
+    pub wrap: f32,
+    /// Scale of the ambient light of the scene.
+    pub ambient: f32,
+
+    /// Strength of the light passing through blades lit from behind.
+    pub translucency: f32,
+    /// Color of the light passing through the blades.
+    pub translucency_color: Color,
+    /// Higher values narrow the glow around the light.
+    pub translucency_power: f32,
+    /// How much the surface normal bends the light passing through.
+    pub translucency_distortion: f32,
+
+    /// Strength of the highlight on the swaying blade faces.
+    pub specular: f32,
+    pub shininess: f32,
+
+    /// Cel shade the blades to match the toon shaded meshes of the scene,
+    /// the light is quantized into `toon_bands` flat steps or looked up in `toon_ramp`.
+    pub toon: bool,
+    pub toon_bands: u32,
+    /// Width of the blend between two bands, as a fraction of a band.
+    pub toon_softness: f32,
+    /// Sampled along x with the light intensity, black to fully lit, replaces the bands when set.
+    pub toon_ramp: Option<Handle<Image>>,
+}
+
+impl Default for GrassLighting {
+    fn default() -> Self {
+        Self {
+            wrap: 0.5,
+            ambient: 1.0,
+
+            translucency: 1.0,
+            translucency_color: Color::rgb_linear(0.35, 0.45, 0.08),
+            translucency_power: 4.0,
+            translucency_distortion: 0.3,
+
+            specular: 0.3,
+            shininess: 24.0,
+
+            toon: false,
+            toon_bands: 3,
+            toon_softness: 0.05,
+            toon_ramp: None,
+        }
+    }
+}
+
+/// Detail of the roots closer than `distance` to a camera.
+#[derive(Debug, Clone, Copy)]
+pub struct GrassLod {
+    pub distance: f32,
+    pub segments: u32,
+    pub blades: u32,
+}
+
+impl Default for GrassConfig {
+    fn default() -> Self {
+        Self {
+            segments: 5,
+            blades: 5,
+            blade_radius: 0.392,
+
+            species: vec![GrassSpecies::default()],
+            species_map: None,
+
+            seed: 0,
+
+            wind_strength: 0.03,
+
+            density_map: None,
+
+            instanced: false,
+            grow_buffers: false,
+
+            frustum_culling: true,
+            occlusion_culling: false,
+
+            lod_bands: vec![
+                GrassLod {
+                    distance: 10.0,
+                    segments: 5,
+                    blades: 5,
+                },
+                GrassLod {
+                    distance: 25.0,
+                    segments: 3,
+                    blades: 3,
+                },
+                GrassLod {
+                    distance: 50.0,
+                    segments: 1,
+                    blades: 1,
+                },
+            ],
+            lod_fade: 5.0,
+
+            fade: false,
+            fade_start: 40.0,
+            fade_end: 50.0,
+
+            cast_shadows: true,
+
+            lighting: GrassLighting::default(),
+
+            trampling: true,
+            trample_recovery: 0.2,
+
+            regrowth: 0.05,
+
+            hue_variation: 0.15,
+            value_variation: 0.2,
+
+            color_map: None,
+            color_map_scale: 32.0,
+        }
+    }
+}
+
+impl GrassConfig {
+    /// Vertices written for a single blade at full detail.
+    pub fn vertices_per_blade(&self) -> u32 {
+        self.segments * 2 + 1
+    }
+
+    /// Indices written for a single blade at full detail, including the strip reset.
+    pub fn indices_per_blade(&self) -> u32 {
+        self.segments * 2 + 2
+    }
+
+    pub fn validate(&self) -> Result<(), GrassConfigError> {
+        if !(1..=MAX_SEGMENTS).contains(&self.segments) {
+            return Err(GrassConfigError::Segments(self.segments));
+        }
+        if !(1..=MAX_BLADES).contains(&self.blades) {
+            return Err(GrassConfigError::Blades(self.blades));
+        }
+        if self.lod_bands.len() > MAX_LOD_BANDS {
+            return Err(GrassConfigError::LodBands(self.lod_bands.len()));
+        }
+
+        if !(1..=MAX_SPECIES).contains(&self.species.len()) {
+            return Err(GrassConfigError::Species(self.species.len()));
+        }
+        for (index, species) in self.species.iter().enumerate() {
+            if species.height_min > species.height_max
+                || species.width_min > species.width_max
+                || species.weight < 0.0
+            {
+                return Err(GrassConfigError::SpeciesShape(index));
+            }
+        }
+        if self.species.iter().all(|species| species.weight <= 0.0) {
+            return Err(GrassConfigError::SpeciesWeight);
+        }
+
+        let mut distance = 0.0;
+        for (index, lod) in self.lod_bands.iter().enumerate() {
+            if lod.distance < distance {
+                return Err(GrassConfigError::LodOrder(index));
+            }
+            if !(1..=self.segments).contains(&lod.segments)
+                || !(1..=self.blades).contains(&lod.blades)
+            {
+                return Err(GrassConfigError::LodDetail(index));
+            }
+            distance = lod.distance;
+        }
+
+        if self.fade && !(0.0..self.fade_end).contains(&self.fade_start) {
+            return Err(GrassConfigError::FadeRange);
+        }
+
+        let lighting = &self.lighting;
+        if lighting.toon && lighting.toon_ramp.is_none() && lighting.toon_bands == 0 {
+            return Err(GrassConfigError::ToonBands);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrassConfigError {
+    Segments(u32),
+    Blades(u32),
+    LodBands(usize),
+    LodOrder(usize),
+    LodDetail(usize),
+    Species(usize),
+    SpeciesShape(usize),
+    SpeciesWeight,
+    FadeRange,
+    ToonBands,
+    BufferSize { size: u64, limit: u64 },
+}
+
+impl fmt::Display for GrassConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Segments(segments) => write!(
+                f,
+                "{} segments per blade, expected 1..={}",
+                segments, MAX_SEGMENTS
+            ),
+            Self::Blades(blades) => {
+                write!(f, "{} blades per root, expected 1..={}", blades, MAX_BLADES)
+            }
+            Self::LodBands(count) => write!(
+                f,
+                "{} lod bands, at most {} are supported",
+                count, MAX_LOD_BANDS
+            ),
+            Self::LodOrder(index) => {
+                write!(f, "lod band {} is closer than the one before it", index)
+            }
+            Self::LodDetail(index) => write!(
+                f,
+                "lod band {} has no segments or blades, or more than the field",
+                index
+            ),
+            Self::Species(count) => write!(f, "{} species, expected 1..={}", count, MAX_SPECIES),
+            Self::SpeciesShape(index) => write!(
+                f,
+                "species {} has an inverted range or a negative weight",
+                index
+            ),
+            Self::SpeciesWeight => write!(f, "every species has a zero weight"),
+            Self::FadeRange => write!(f, "the fade has to start before it ends"),
+            Self::ToonBands => write!(f, "toon shading needs at least one band or a ramp"),
+            Self::BufferSize { size, limit } => write!(
+                f,
+                "output buffers need {} bytes, the device allows {}",
+                size, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GrassConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(GrassConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn invalid_lod_bands_are_rejected() {
+        let mut config = GrassConfig::default();
+        config.lod_bands.swap(0, 1);
+        assert_eq!(config.validate(), Err(GrassConfigError::LodOrder(1)));
+
+        let mut config = GrassConfig::default();
+        config.lod_bands[1].blades = 0;
+        assert_eq!(config.validate(), Err(GrassConfigError::LodDetail(1)));
+
+        let mut config = GrassConfig::default();
+        config.lod_bands[0].segments = config.segments + 1;
+        assert_eq!(config.validate(), Err(GrassConfigError::LodDetail(0)));
+    }
+
+    #[test]
+    fn invalid_species_are_rejected() {
+        let mut config = GrassConfig::default();
+        config.species.clear();
+        assert_eq!(config.validate(), Err(GrassConfigError::Species(0)));
+
+        let mut config = GrassConfig::default();
+        config.species[0].height_min = config.species[0].height_max + 1.0;
+        assert_eq!(config.validate(), Err(GrassConfigError::SpeciesShape(0)));
+
+        let mut config = GrassConfig::default();
+        config.species[0].weight = 0.0;
+        assert_eq!(config.validate(), Err(GrassConfigError::SpeciesWeight));
+    }
+}
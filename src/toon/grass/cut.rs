@@ -0,0 +1,139 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+
+/// Cuts every grass field inside `shape` down to `height`.
+///
+/// Cut blades stay short and regrow at the `regrowth` rate of their field.
+#[derive(Debug, Clone, Copy)]
+pub struct GrassCut {
+    pub shape: GrassCutShape,
+    /// Fraction of the full blade height left standing, `0.0` cuts to the ground.
+    pub height: f32,
+}
+
+/// World space region of a `GrassCut`.
+#[derive(Debug, Clone, Copy)]
+pub enum GrassCutShape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        rotation: Quat,
+        half_extents: Vec3,
+    },
+}
+
+const SHAPE_SPHERE: u32 = 0;
+const SHAPE_BOX: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GpuCut {
+    shape: u32,
+    height: f32,
+    _pad: [u32; 2],
+    /// Center and radius of spheres.
+    center: [f32; 4],
+    half_extents: [f32; 4],
+    /// Inverse rotation of boxes.
+    rotation: [f32; 4],
+}
+
+impl From<GrassCut> for GpuCut {
+    fn from(cut: GrassCut) -> Self {
+        let height = cut.height.clamp(0.0, 1.0);
+        match cut.shape {
+            GrassCutShape::Sphere { center, radius } => Self {
+                shape: SHAPE_SPHERE,
+                height,
+                _pad: [0; 2],
+                center: center.extend(radius).to_array(),
+                half_extents: [0.0; 4],
+                rotation: Quat::IDENTITY.to_array(),
+            },
+            GrassCutShape::Box {
+                center,
+                rotation,
+                half_extents,
+            } => Self {
+                shape: SHAPE_BOX,
+                height,
+                _pad: [0; 2],
+                center: center.extend(0.0).to_array(),
+                half_extents: half_extents.extend(0.0).to_array(),
+                rotation: rotation.inverse().to_array(),
+            },
+        }
+    }
+}
+
+/// Cuts sent this frame, shared by every grass field.
+#[derive(Default)]
+pub struct GrassCuts(pub Vec<GpuCut>);
+
+pub fn collect_grass_cuts(mut events: EventReader<GrassCut>, mut cuts: ResMut<GrassCuts>) {
+    cuts.0.clear();
+    cuts.0.extend(events.iter().copied().map(GpuCut::from));
+}
+
+pub fn extract_grass_cuts(mut commands: Commands, cuts: Extract<Res<GrassCuts>>) {
+    commands.insert_resource(GrassCuts(cuts.0.clone()));
+}
+
+/// Storage buffer with a `u32` count padded to 16 bytes, followed by the cuts.
+pub struct GrassCutsBuffer {
+    pub buffer: Buffer,
+    capacity: usize,
+}
+
+const HEADER_SIZE: usize = 16;
+
+impl GrassCutsBuffer {
+    fn create(device: &RenderDevice, capacity: usize) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grass_cuts"),
+            size: (HEADER_SIZE + capacity * size_of::<GpuCut>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer, capacity }
+    }
+}
+
+impl FromWorld for GrassCutsBuffer {
+    fn from_world(world: &mut World) -> Self {
+        Self::create(world.resource::<RenderDevice>(), 4)
+    }
+}
+
+pub fn prepare_grass_cuts(
+    cuts: Res<GrassCuts>,
+    mut buffer: ResMut<GrassCutsBuffer>,
+    device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if cuts.0.len() > buffer.capacity {
+        *buffer = GrassCutsBuffer::create(&device, cuts.0.len().next_power_of_two());
+    }
+
+    let header = [cuts.0.len() as u32, 0, 0, 0];
+    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&header));
+    if !cuts.0.is_empty() {
+        render_queue.write_buffer(
+            &buffer.buffer,
+            HEADER_SIZE as wgpu::BufferAddress,
+            bytemuck::cast_slice(&cuts.0),
+        );
+    }
+}
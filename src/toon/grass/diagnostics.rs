@@ -0,0 +1,65 @@
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+    render::RenderApp,
+};
+
+use super::GrassStats;
+
+/// Publishes the blades, vertices and indices produced by the grass compute pass
+/// through `Diagnostics`.
+///
+/// The counters are read back from the gpu a few frames late.
+pub struct GrassDiagnosticsPlugin;
+
+impl GrassDiagnosticsPlugin {
+    pub const BLADES: DiagnosticId =
+        DiagnosticId::from_u128(0x5a1e_2c3b_8d4f_4e61_9b07_31c8_6f2a_1d01);
+    pub const VERTICES: DiagnosticId =
+        DiagnosticId::from_u128(0x5a1e_2c3b_8d4f_4e61_9b07_31c8_6f2a_1d02);
+    pub const INDICES: DiagnosticId =
+        DiagnosticId::from_u128(0x5a1e_2c3b_8d4f_4e61_9b07_31c8_6f2a_1d03);
+}
+
+impl Plugin for GrassDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let stats = GrassStats::default();
+
+        app.insert_resource(stats.clone())
+            .add_startup_system(setup_diagnostics)
+            .add_system(publish_diagnostics);
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.insert_resource(stats);
+        }
+    }
+}
+
+fn setup_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(
+        GrassDiagnosticsPlugin::BLADES,
+        "grass_blades",
+        20,
+    ));
+    diagnostics.add(Diagnostic::new(
+        GrassDiagnosticsPlugin::VERTICES,
+        "grass_vertices",
+        20,
+    ));
+    diagnostics.add(Diagnostic::new(
+        GrassDiagnosticsPlugin::INDICES,
+        "grass_indices",
+        20,
+    ));
+}
+
+fn publish_diagnostics(stats: Res<GrassStats>, mut diagnostics: ResMut<Diagnostics>) {
+    let frame = match stats.0.lock().unwrap().take() {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    diagnostics.add_measurement(GrassDiagnosticsPlugin::BLADES, frame.blades as f64);
+    diagnostics.add_measurement(GrassDiagnosticsPlugin::VERTICES, frame.vertices as f64);
+    diagnostics.add_measurement(GrassDiagnosticsPlugin::INDICES, frame.indices as f64);
+}
@@ -0,0 +1,104 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+
+/// Bends the grass within `radius` away from the entity.
+#[derive(Clone, Component)]
+pub struct GrassInteractor {
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl Default for GrassInteractor {
+    fn default() -> Self {
+        Self {
+            radius: 0.75,
+            strength: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GpuInteractor {
+    position: [f32; 3],
+    radius: f32,
+    strength: f32,
+    _pad: [f32; 3],
+}
+
+/// World space interactors of this frame, shared by every grass field.
+#[derive(Default)]
+pub struct GrassInteractors(pub Vec<GpuInteractor>);
+
+pub fn extract_grass_interactors(
+    mut commands: Commands,
+    query: Extract<Query<(&GrassInteractor, &GlobalTransform)>>,
+) {
+    let interactors = query
+        .iter()
+        .map(|(interactor, transform)| GpuInteractor {
+            position: transform.translation().into(),
+            radius: interactor.radius,
+            strength: interactor.strength,
+            _pad: [0.0; 3],
+        })
+        .collect();
+
+    commands.insert_resource(GrassInteractors(interactors));
+}
+
+/// Storage buffer with a `u32` count padded to 16 bytes, followed by the interactors.
+pub struct GrassInteractorsBuffer {
+    pub buffer: Buffer,
+    capacity: usize,
+}
+
+const HEADER_SIZE: usize = 16;
+
+impl GrassInteractorsBuffer {
+    fn create(device: &RenderDevice, capacity: usize) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grass_interactors"),
+            size: (HEADER_SIZE + capacity * size_of::<GpuInteractor>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer, capacity }
+    }
+}
+
+impl FromWorld for GrassInteractorsBuffer {
+    fn from_world(world: &mut World) -> Self {
+        Self::create(world.resource::<RenderDevice>(), 16)
+    }
+}
+
+pub fn prepare_grass_interactors(
+    interactors: Res<GrassInteractors>,
+    mut buffer: ResMut<GrassInteractorsBuffer>,
+    device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    if interactors.0.len() > buffer.capacity {
+        *buffer = GrassInteractorsBuffer::create(&device, interactors.0.len().next_power_of_two());
+    }
+
+    let header = [interactors.0.len() as u32, 0, 0, 0];
+    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&header));
+    if !interactors.0.is_empty() {
+        render_queue.write_buffer(
+            &buffer.buffer,
+            HEADER_SIZE as wgpu::BufferAddress,
+            bytemuck::cast_slice(&interactors.0),
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::{
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_phase::{EntityRenderCommand, RenderCommandResult, TrackedRenderPass},
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::FallbackImage,
+    },
+    utils::HashMap,
+};
+use bytemuck::{Pod, Zeroable};
+use std::mem::size_of;
+
+use super::render::GrassRenderPipeline;
+use super::{GrassBuffers, GrassLighting};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GrassMaterialUniform {
+    translucency_color: [f32; 4],
+
+    wrap: f32,
+    ambient: f32,
+    translucency: f32,
+    translucency_power: f32,
+
+    translucency_distortion: f32,
+    specular: f32,
+    shininess: f32,
+    toon_bands: u32,
+
+    toon_softness: f32,
+    toon_ramp: u32,
+    _pad_toon: [u32; 2],
+}
+
+impl GrassMaterialUniform {
+    pub fn new(lighting: &GrassLighting) -> Self {
+        Self {
+            translucency_color: lighting.translucency_color.as_linear_rgba_f32(),
+
+            wrap: lighting.wrap.max(0.0),
+            ambient: lighting.ambient,
+            translucency: lighting.translucency,
+            translucency_power: lighting.translucency_power.max(0.001),
+
+            translucency_distortion: lighting.translucency_distortion,
+            specular: lighting.specular,
+            shininess: lighting.shininess.max(1.0),
+            toon_bands: lighting.toon_bands.max(1),
+
+            toon_softness: lighting.toon_softness.clamp(0.0, 1.0),
+            toon_ramp: lighting.toon_ramp.is_some() as u32,
+            _pad_toon: [0; 2],
+        }
+    }
+}
+
+pub const MATERIAL_LAYOUT: BindGroupLayoutDescriptor = BindGroupLayoutDescriptor {
+    label: Some("grass_material"),
+    entries: &[
+        BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(size_of::<GrassMaterialUniform>() as u64),
+            },
+            count: None,
+        },
+        // toon ramp
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        },
+    ],
+};
+
+/// Render bind groups of all grass fields, keyed by the field entity.
+#[derive(Default)]
+pub struct GrassMaterialBindGroups(pub HashMap<Entity, BindGroup>);
+
+pub fn queue_grass_material_bind_groups(
+    mut bind_groups: ResMut<GrassMaterialBindGroups>,
+    pipeline: Res<GrassRenderPipeline>,
+    device: Res<RenderDevice>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    buffers: Res<GrassBuffers>,
+) {
+    bind_groups.0.clear();
+    for (entity, data) in buffers.0.iter() {
+        // plain white lights everything fully while the ramp is loading
+        let (ramp, sampler) = match data
+            .config
+            .lighting
+            .toon_ramp
+            .as_ref()
+            .and_then(|handle| images.get(handle))
+        {
+            Some(image) => (&image.texture_view, &image.sampler),
+            None => (&fallback_image.texture_view, &fallback_image.sampler),
+        };
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("grass_material"),
+            layout: &pipeline.material_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: data.material_buf.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(ramp),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        bind_groups.0.insert(*entity, bind_group);
+    }
+}
+
+pub struct SetGrassMaterialBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetGrassMaterialBindGroup<I> {
+    type Param = SRes<GrassMaterialBindGroups>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        match bind_groups.into_inner().0.get(&item) {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
@@ -1,53 +1,191 @@
 use bevy::{
-    core_pipeline::core_3d::Opaque3d,
+    core_pipeline::core_3d::{graph, Opaque3d},
     ecs::{query::QueryItem, system::lifetimeless::Read},
+    pbr::{MeshUniform, Shadow},
     prelude::*,
-    render::{extract_component::ExtractComponent, render_phase::AddRenderCommand},
+    render::{
+        extract_component::ExtractComponent,
+        render_phase::{AddRenderCommand, DrawFunctions},
+    },
     render::{
         extract_component::ExtractComponentPlugin,
         extract_resource::{ExtractResource, ExtractResourcePlugin},
         main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
         render_graph::RenderGraph,
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
+        texture::FallbackImage,
         RenderApp, RenderStage,
     },
+    transform::TransformSystem,
+    utils::{HashMap, HashSet},
 };
 use bytemuck::{Pod, Zeroable};
 use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::normal_pass::Normal3d;
 
 mod compute;
+mod config;
+mod cut;
+mod diagnostics;
+mod interactor;
+mod material;
+mod normal;
+mod occlusion;
+mod readback;
 mod render;
+mod shadow;
+mod source;
+mod stream;
+mod view;
+mod wind;
 
 pub use self::compute::{GrassComputeNode, GrassComputePipeline};
+pub use self::config::{
+    GrassConfig, GrassConfigError, GrassLighting, GrassLod, GrassSpecies, MAX_LOD_BANDS,
+    MAX_SPECIES,
+};
+pub use self::cut::{GrassCut, GrassCutShape, GrassCuts, GrassCutsBuffer};
+pub use self::diagnostics::GrassDiagnosticsPlugin;
+pub use self::interactor::{GrassInteractor, GrassInteractors, GrassInteractorsBuffer};
+pub use self::material::{
+    GrassMaterialBindGroups, GrassMaterialUniform, SetGrassMaterialBindGroup,
+};
+pub use self::normal::GrassNormalPipeline;
+pub use self::occlusion::{
+    draw_grass_graph, GrassDepthPyramid, GrassDepthPyramidNode, GrassDepthPyramidPipeline,
+};
+pub use self::readback::{
+    GrassFrameStats, GrassOverflow, GrassOverflows, GrassReadback, GrassReadbackNode, GrassStats,
+};
 pub use self::render::{DrawGrass, GrassRenderPipeline};
+pub use self::shadow::{DrawGrassShadow, GrassShadowPipeline};
+pub use self::source::{GrassSource, GrassSourceVertex, GrassSurface};
+pub use self::stream::{GrassStreaming, GrassTile};
+pub use self::view::{GrassViews, GrassViewsBuffer};
+pub use self::wind::{GrassWindBuffer, Wind};
 
 pub struct GrassPlugin;
 
 impl Plugin for GrassPlugin {
     fn build(&self, app: &mut App) {
-        let render_device = app.world.resource::<RenderDevice>();
-        let buffer = GrassData::new(render_device);
-
         app.add_plugin(ExtractComponentPlugin::<Grass>::default());
         app.add_plugin(ExtractResourcePlugin::<ExtractedTime>::default());
+        app.add_plugin(ExtractResourcePlugin::<Wind>::default());
+        app.init_resource::<Wind>();
+        app.add_event::<GrassCut>();
+        app.init_resource::<GrassCuts>();
+        app.add_startup_system(self::wind::setup_turbulence);
+        app.add_system_to_stage(CoreStage::PostUpdate, self::source::scatter_grass);
+        app.add_system_to_stage(CoreStage::PostUpdate, self::cut::collect_grass_cuts);
+        app.add_system_to_stage(
+            CoreStage::PostUpdate,
+            self::stream::stream_grass_tiles.before(TransformSystem::TransformPropagate),
+        );
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
-            .insert_resource(buffer)
+            .init_resource::<GrassBuffers>()
+            .init_resource::<GrassViews>()
+            .init_resource::<GrassViewsBuffer>()
+            .init_resource::<GrassInteractors>()
+            .init_resource::<GrassInteractorsBuffer>()
+            .init_resource::<GrassWindBuffer>()
+            .init_resource::<GrassCuts>()
+            .init_resource::<GrassCutsBuffer>()
+            .init_resource::<GrassDepthPyramid>()
+            .init_resource::<GrassReadback>()
+            .init_resource::<GrassOverflows>()
+            .init_resource::<GrassMaterialBindGroups>()
             .add_render_command::<Opaque3d, DrawGrass>()
-            //.add_render_command::<super::normal_pass::Normal3d, DrawGrass>()
+            .add_render_command::<Shadow, DrawGrassShadow>()
+            // opt-in per field through `NormalPassMaterial`, the draw functions may not exist yet
+            .init_resource::<DrawFunctions<Normal3d>>()
+            .add_render_command::<Normal3d, DrawGrass>()
             .init_resource::<GrassComputePipeline>()
             .init_resource::<GrassRenderPipeline>()
             .init_resource::<SpecializedRenderPipelines<GrassRenderPipeline>>()
-            .add_system_to_stage(RenderStage::Prepare, ExtractedTime::prepare)
+            .init_resource::<GrassNormalPipeline>()
+            .init_resource::<SpecializedRenderPipelines<GrassNormalPipeline>>()
+            .init_resource::<GrassShadowPipeline>()
+            .init_resource::<SpecializedRenderPipelines<GrassShadowPipeline>>()
+            .init_resource::<GrassDepthPyramidPipeline>()
+            .add_system_to_stage(RenderStage::Prepare, self::readback::prepare_grass_readback)
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_grass_buffers.after(self::readback::prepare_grass_readback),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                self::readback::prepare_grass_readback_slot.after(prepare_grass_buffers),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                ExtractedTime::prepare.after(prepare_grass_buffers),
+            )
             .add_system_to_stage(RenderStage::Extract, self::render::extract_grass)
+            .add_system_to_stage(RenderStage::Extract, self::view::extract_grass_views)
+            .add_system_to_stage(RenderStage::Prepare, self::occlusion::prepare_depth_pyramid)
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                self::view::prepare_grass_views.after(self::occlusion::prepare_depth_pyramid),
+            )
+            .add_system_to_stage(
+                RenderStage::Extract,
+                self::interactor::extract_grass_interactors,
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                self::interactor::prepare_grass_interactors,
+            )
+            .add_system_to_stage(RenderStage::Prepare, self::wind::prepare_wind)
+            .add_system_to_stage(RenderStage::Extract, self::cut::extract_grass_cuts)
+            .add_system_to_stage(RenderStage::Prepare, self::cut::prepare_grass_cuts)
             .add_system_to_stage(RenderStage::Queue, self::render::queue_grass)
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group);
+            .add_system_to_stage(RenderStage::Queue, self::shadow::queue_grass_shadows)
+            .add_system_to_stage(RenderStage::Queue, self::normal::queue_grass_normals)
+            .add_system_to_stage(
+                RenderStage::Queue,
+                self::occlusion::queue_depth_pyramid_bind_groups,
+            )
+            .add_system_to_stage(RenderStage::Queue, queue_bind_group)
+            .add_system_to_stage(
+                RenderStage::Queue,
+                self::material::queue_grass_material_bind_groups,
+            )
+            .add_system_to_stage(RenderStage::Cleanup, self::readback::map_grass_readback);
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
         render_graph.add_node("grass", GrassComputeNode::default());
         render_graph.add_node_edge("grass", CAMERA_DRIVER).unwrap();
+        render_graph.add_node("grass_readback", GrassReadbackNode::default());
+        render_graph
+            .add_node_edge("grass", "grass_readback")
+            .unwrap();
+
+        // the pyramid is built from the depth of the main pass, for the grass of the next frame
+        let draw_3d_graph = render_graph.get_sub_graph_mut(graph::NAME).unwrap();
+        draw_3d_graph.add_node(
+            draw_grass_graph::node::DEPTH_PYRAMID,
+            GrassDepthPyramidNode::default(),
+        );
+        draw_3d_graph
+            .add_node_edge(
+                graph::node::MAIN_PASS,
+                draw_grass_graph::node::DEPTH_PYRAMID,
+            )
+            .unwrap();
+        draw_3d_graph
+            .add_slot_edge(
+                draw_3d_graph.input_node().unwrap().id,
+                graph::input::VIEW_ENTITY,
+                draw_grass_graph::node::DEPTH_PYRAMID,
+                GrassDepthPyramidNode::IN_VIEW,
+            )
+            .unwrap();
     }
 }
 
@@ -70,13 +208,29 @@ struct ExtractedTime {
 }
 
 impl ExtractedTime {
-    // write the extracted time into the corresponding uniform buffer
-    fn prepare(time: Res<ExtractedTime>, data: Res<GrassData>, render_queue: Res<RenderQueue>) {
-        render_queue.write_buffer(
-            &data.params_buf,
-            0,
-            bevy::core::cast_slice(&[time.seconds_since_startup]),
-        );
+    // write the extracted time, transform and the latest config into the corresponding uniform buffers
+    fn prepare(
+        time: Res<ExtractedTime>,
+        buffers: Res<GrassBuffers>,
+        render_queue: Res<RenderQueue>,
+        query: Query<(Entity, &MeshUniform), With<Grass>>,
+    ) {
+        for (entity, mesh_uniform) in query.iter() {
+            let data = match buffers.0.get(&entity) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            let uniform = GrassUniform {
+                time: time.seconds_since_startup,
+                model: mesh_uniform.transform.to_cols_array_2d(),
+                ..GrassUniform::new(data.src_vertices_len as u32, &data.config, &data.capacity)
+            };
+            render_queue.write_buffer(&data.params_buf, 0, bytemuck::bytes_of(&uniform));
+
+            let material = GrassMaterialUniform::new(&data.config.lighting);
+            render_queue.write_buffer(&data.material_buf, 0, bytemuck::bytes_of(&material));
+        }
     }
 }
 
@@ -92,29 +246,6 @@ impl ExtractResource for ExtractedTime {
 
 const WORKGROUPS: u32 = 256;
 
-pub struct GrassConfig {
-    pub blades: u32,
-    pub blade_radius: f32,
-    pub blade_forward: f32,
-    pub blade_curve: f32,
-    pub wind_speed: f32,
-    pub wind_strength: f32,
-}
-
-impl Default for GrassConfig {
-    fn default() -> Self {
-        Self {
-            blades: 5,
-            blade_radius: 0.392,
-            blade_forward: 0.38,
-            blade_curve: 2.1,
-
-            wind_speed: 1.0,
-            wind_strength: 0.015,
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct GrassUniform {
@@ -122,13 +253,161 @@ pub struct GrassUniform {
     length: u32,
 
     blades: u32,
+    segments: u32,
 
     blade_radius: f32,
-    blade_forward: f32,
-    blade_curve: f32,
+    /// Tallest blade and furthest lean of all species, for the culling bounds.
+    max_height: f32,
+    max_forward: f32,
 
-    wind_speed: f32,
     wind_strength: f32,
+
+    model: [[f32; 4]; 4],
+
+    frustum_culling: u32,
+
+    lod_count: u32,
+    lod_fade: f32,
+    /// Sits in the padding before the lod bands.
+    occlusion_culling: u32,
+    lod_bands: [GrassLodUniform; MAX_LOD_BANDS],
+
+    trampling: u32,
+    trample_recovery: f32,
+    regrowth: f32,
+    _pad_trample: u32,
+
+    hue_variation: f32,
+    value_variation: f32,
+    color_map_scale: f32,
+    seed: u32,
+
+    species_count: u32,
+    instanced: u32,
+    _pad_species: [u32; 2],
+    species: [GrassSpeciesUniform; MAX_SPECIES],
+
+    /// Size of the output buffers, blades past it are dropped.
+    capacity_blades: u32,
+    capacity_vertices: u32,
+    capacity_indices: u32,
+    _pad_capacity: u32,
+
+    fade: u32,
+    fade_start: f32,
+    fade_end: f32,
+    /// Roots culled for the cameras are kept inside the frustums of the shadow casting lights.
+    cast_shadows: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+struct GrassSpeciesUniform {
+    base_color: [f32; 4],
+    tip_color: [f32; 4],
+    height_min: f32,
+    height_max: f32,
+    width_min: f32,
+    width_max: f32,
+    bottom_width: f32,
+    forward: f32,
+    curve: f32,
+    weight: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+struct GrassLodUniform {
+    distance: f32,
+    segments: u32,
+    blades: u32,
+    _pad: u32,
+}
+
+impl GrassUniform {
+    fn new(length: u32, config: &GrassConfig, capacity: &GrassCapacity) -> Self {
+        let mut lod_bands = [GrassLodUniform::default(); MAX_LOD_BANDS];
+        for (dst, lod) in lod_bands.iter_mut().zip(&config.lod_bands) {
+            *dst = GrassLodUniform {
+                distance: lod.distance,
+                segments: lod.segments,
+                blades: lod.blades,
+                _pad: 0,
+            };
+        }
+
+        let mut species = [GrassSpeciesUniform::default(); MAX_SPECIES];
+        for (dst, kind) in species.iter_mut().zip(&config.species) {
+            *dst = GrassSpeciesUniform {
+                base_color: kind.base_color.as_linear_rgba_f32(),
+                tip_color: kind.tip_color.as_linear_rgba_f32(),
+                height_min: kind.height_min,
+                height_max: kind.height_max,
+                width_min: kind.width_min,
+                width_max: kind.width_max,
+                bottom_width: kind.bottom_width,
+                forward: kind.forward,
+                curve: kind.curve,
+                weight: kind.weight.max(0.0),
+            };
+        }
+
+        let species_count = config.species.len().min(MAX_SPECIES);
+        let max_height = species[..species_count]
+            .iter()
+            .fold(0.0f32, |max, kind| max.max(kind.height_max));
+        let max_forward = species[..species_count]
+            .iter()
+            .fold(0.0f32, |max, kind| max.max(kind.forward.abs()));
+
+        Self {
+            time: 0.0,
+            length,
+
+            blades: config.blades,
+            segments: config.segments,
+
+            blade_radius: config.blade_radius,
+            max_height,
+            max_forward,
+
+            wind_strength: config.wind_strength,
+
+            model: Mat4::IDENTITY.to_cols_array_2d(),
+
+            frustum_culling: config.frustum_culling as u32,
+
+            lod_count: config.lod_bands.len().min(MAX_LOD_BANDS) as u32,
+            lod_fade: config.lod_fade,
+            occlusion_culling: config.occlusion_culling as u32,
+            lod_bands,
+
+            trampling: config.trampling as u32,
+            trample_recovery: config.trample_recovery,
+            regrowth: config.regrowth,
+            _pad_trample: 0,
+
+            hue_variation: config.hue_variation,
+            value_variation: config.value_variation,
+            color_map_scale: config.color_map_scale,
+            seed: config.seed,
+
+            species_count: species_count as u32,
+            instanced: config.instanced as u32,
+            _pad_species: [0; 2],
+            species,
+
+            capacity_blades: capacity.blades.min(u32::MAX as u64) as u32,
+            capacity_vertices: capacity.vertices.min(u32::MAX as u64) as u32,
+            capacity_indices: capacity.indices.min(u32::MAX as u64) as u32,
+            _pad_capacity: 0,
+
+            fade: config.fade as u32,
+            fade_start: config.fade_start,
+            fade_end: config.fade_end,
+            cast_shadows: config.cast_shadows as u32,
+        }
+    }
 }
 
 #[repr(C)]
@@ -136,99 +415,325 @@ pub struct GrassUniform {
 pub struct DstVertex {
     position: [f32; 3],
     normal: [f32; 3],
+    /// Normal of the bent blade, interpolated so the highlights follow the curve.
+    face: [f32; 3],
     texcoord: [f32; 2],
+    /// Square root of the linear color, so the dark greens keep their precision in 8 bits.
+    color: [u8; 4],
+}
+
+/// Blade written by the compute pass in the instanced mode, expanded by the vertex shader.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BladeInstance {
+    position_height: [f32; 4],
+    normal_width: [f32; 4],
+    displacement_forward: [f32; 4],
+    shape: [f32; 4],
+    /// Packed base and tip colors, segments and padding.
+    data: [u32; 4],
 }
 
 #[derive(Default, Bundle)]
 pub struct GrassBundle {
     pub grass: Grass,
+    pub config: GrassConfig,
+    pub surface: GrassSurface,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct GrassSourceVertex {
-    position: [f32; 3],
-    normal: [f32; 3],
+/// GPU buffers of every grass field, keyed by the field entity.
+#[derive(Default)]
+pub struct GrassBuffers(pub HashMap<Entity, GrassData>);
+
+/// Creates buffers for newly scattered grass fields and drops the ones of despawned fields.
+///
+/// `GrassConfig` and `GrassSource` are only extracted on frames where they changed,
+/// so their presence means the field has to be refreshed.
+/// A new source always comes with the config, a config seen before any source is dropped.
+/// Invalid configs are reported and ignored, the field keeps its last valid one or the default.
+/// Fields that dropped blades are reallocated with the capacity they asked for.
+fn prepare_grass_buffers(
+    mut buffers: ResMut<GrassBuffers>,
+    mut overflows: ResMut<GrassOverflows>,
+    // fields that failed to grow, reported once
+    mut stuck: Local<HashSet<Entity>>,
+    device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    query: Query<(Entity, Option<&GrassSource>, Option<&GrassConfig>), With<Grass>>,
+) {
+    buffers.0.retain(|entity, _| query.contains(*entity));
+    stuck.retain(|entity| buffers.0.contains_key(entity));
+
+    for (entity, new_source, new_config) in query.iter() {
+        if new_source.is_none() && new_config.is_none() {
+            continue;
+        }
+
+        let current = buffers.0.get(&entity);
+
+        let config = new_config
+            .or_else(|| current.map(|data| &data.config))
+            .cloned()
+            .unwrap_or_default();
+
+        // a source extracted along with an invalid config is only seen once, so it still goes through
+        let config = match config.validate() {
+            Ok(()) => config,
+            Err(err) => {
+                error!("grass field {:?} has an invalid config: {}", entity, err);
+                current.map(|data| data.config.clone()).unwrap_or_default()
+            }
+        };
+
+        // the output buffers are sized for the blades and segments at full detail
+        let resize = current.map_or(true, |data| {
+            data.config.segments != config.segments
+                || data.config.blades != config.blades
+                || data.config.instanced != config.instanced
+        });
+
+        let source = match new_source.or_else(|| current.map(|data| &data.source)) {
+            Some(source) => source.clone(),
+            None => continue,
+        };
+
+        if new_source.is_none() && !resize {
+            if let Some(data) = buffers.0.get_mut(&entity) {
+                data.config = config;
+            }
+            continue;
+        }
+
+        if source.vertices.is_empty() {
+            buffers.0.remove(&entity);
+            continue;
+        }
+
+        // recycled streaming tiles keep their buffers, only the roots are replaced
+        if let Some(data) = buffers.0.get_mut(&entity) {
+            if !resize && data.src_vertices_len == source.vertices.len() {
+                // a re-scatter to the same roots keeps the trails and cuts
+                if !data.source.same_roots(&source) {
+                    render_queue.write_buffer(
+                        &data.src_vertices_buf,
+                        0,
+                        bytemuck::cast_slice(source.vertices.as_slice()),
+                    );
+                    let trample = vec![0u8; data.src_vertices_len * size_of::<[f32; 4]>()];
+                    render_queue.write_buffer(&data.trample_buf, 0, &trample);
+                    let cut = vec![0u8; data.src_vertices_len * size_of::<[f32; 2]>()];
+                    render_queue.write_buffer(&data.cut_buf, 0, &cut);
+                }
+
+                data.source = source;
+                data.config = config;
+                continue;
+            }
+        }
+
+        match GrassData::new(&device, source, config, GrassCapacity::default()) {
+            Ok(data) => {
+                if let Some(previous) = buffers.0.get(&entity) {
+                    data.carry_state(previous, &device, &render_queue);
+                }
+                buffers.0.insert(entity, data);
+                stuck.remove(&entity);
+            }
+            Err(err) => {
+                error!("grass field {:?} can not be allocated: {}", entity, err);
+                buffers.0.remove(&entity);
+            }
+        }
+    }
+
+    // reports may be late, only the ones about the buffers in use still hold
+    for (entity, overflow) in overflows.0.drain() {
+        let required = overflow.required;
+        let data = match buffers.0.get(&entity) {
+            Some(data)
+                if data.generation == overflow.generation && !data.capacity.contains(&required) =>
+            {
+                data
+            }
+            _ => continue,
+        };
+
+        let capacity = data.capacity.max(&required.next_power_of_two());
+        let (source, config) = (data.source.clone(), data.config.clone());
+        match GrassData::new(&device, source, config, capacity) {
+            Ok(grown) => {
+                // the trails and cuts are kept
+                grown.carry_state(data, &device, &render_queue);
+                buffers.0.insert(entity, grown);
+            }
+            Err(err) => {
+                if stuck.insert(entity) {
+                    error!("grass field {:?} can not be grown: {}", entity, err);
+                }
+            }
+        }
+    }
 }
 
 pub struct GrassData {
+    pub config: GrassConfig,
+    pub source: GrassSource,
     pub params_buf: Buffer,
+    /// Lighting of the blades, read by the render pass.
+    pub material_buf: Buffer,
+
+    /// Latest push and the time it happened for every root, kept across frames and rebuilds.
+    pub trample_buf: Buffer,
+    /// Latest cut and the time it happened for every root, kept across frames and rebuilds.
+    pub cut_buf: Buffer,
 
     pub src_vertices_buf: Buffer,
     pub src_vertices_len: usize,
 
+    /// Vertices, blades and indices reserved by the compute pass, and the blades it dropped.
     pub vertex_buffer_len: Buffer,
 
     pub vertex_buffer: Buffer,
+    /// Blades of the instanced mode, a single unused record otherwise.
+    pub instance_buffer: Buffer,
     pub index_buffer: Buffer,
     pub indirect_buffer: Buffer,
+    pub capacity: GrassCapacity,
+    /// Unique to these buffers, counters read back from other buffers of the field are stale.
+    pub generation: u64,
 }
 
-impl GrassData {
-    fn new(device: &RenderDevice) -> Self {
-        fn create_source(x_size: usize, z_size: usize, scale: f32) -> Vec<GrassSourceVertex> {
-            let (x_size, z_size) = (x_size * 2, z_size * 2);
-            let capacity = (x_size + 1) * (z_size + 1);
-
-            let mut vertices = Vec::with_capacity(capacity);
-
-            let hx = x_size as i32 / 2;
-            let hz = z_size as i32 / 2;
-            for z in 0..=z_size as i32 {
-                for x in 0..=x_size as i32 {
-                    let (x, z) = (x - hx, z - hz);
-                    vertices.push(GrassSourceVertex {
-                        position: [x as f32 * scale, 0.0, z as f32 * scale],
-                        normal: [0.0, 1.0, 0.0],
-                    });
-                }
-            }
+/// Blades, vertices and indices the output buffers of a field are sized for.
+///
+/// In the instanced mode the vertices and indices are the ones expanded by the vertex shader.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GrassCapacity {
+    pub blades: u64,
+    pub vertices: u64,
+    pub indices: u64,
+}
 
+impl GrassCapacity {
+    /// Every blade of every root at full detail.
+    fn new(roots: usize, config: &GrassConfig) -> Self {
+        let blades = (roots * config.blades as usize) as u64;
+        let vertices = blades * config.vertices_per_blade() as u64;
+        let indices = if config.instanced {
             vertices
+        } else {
+            blades * config.indices_per_blade() as u64
+        };
+
+        Self {
+            blades,
+            vertices,
+            indices,
         }
+    }
 
-        fn indices(blades: u32, segments: u32) -> Vec<u32> {
-            let vertices_per_blade = segments * 2 + 1;
-            let capacity = blades * (vertices_per_blade + 1);
+    fn max(&self, other: &Self) -> Self {
+        Self {
+            blades: self.blades.max(other.blades),
+            vertices: self.vertices.max(other.vertices),
+            indices: self.indices.max(other.indices),
+        }
+    }
 
-            let mut indices = Vec::with_capacity(capacity as usize);
-            for blade in 0..blades {
-                let start = vertices_per_blade * blade;
-                indices.extend((0..vertices_per_blade).map(|i| start + i));
-                indices.push(u32::MAX); // reset strip
-            }
+    fn contains(&self, other: &Self) -> bool {
+        self.blades >= other.blades
+            && self.vertices >= other.vertices
+            && self.indices >= other.indices
+    }
+
+    fn next_power_of_two(&self) -> Self {
+        Self {
+            blades: self.blades.next_power_of_two(),
+            vertices: self.vertices.next_power_of_two(),
+            indices: self.indices.next_power_of_two(),
+        }
+    }
+}
+
+/// Generation of the next buffers created for a field.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+impl GrassData {
+    fn new(
+        device: &RenderDevice,
+        source: GrassSource,
+        config: GrassConfig,
+        min_capacity: GrassCapacity,
+    ) -> Result<Self, GrassConfigError> {
+        let src_vertices_len = source.vertices.len();
 
-            assert_eq!(indices.capacity(), indices.len());
+        // only the buffers of the current mode are sized for every blade
+        let capacity = GrassCapacity::new(src_vertices_len, &config).max(&min_capacity);
+        let (vertices_size, indices_size, instances_size) = if config.instanced {
+            (
+                size_of::<DstVertex>() as u64,
+                config.vertices_per_blade() as u64 * size_of::<u32>() as u64,
+                capacity.blades * size_of::<BladeInstance>() as u64,
+            )
+        } else {
+            (
+                capacity.vertices * size_of::<DstVertex>() as u64,
+                capacity.indices * size_of::<u32>() as u64,
+                size_of::<BladeInstance>() as u64,
+            )
+        };
 
-            indices
+        let size = vertices_size.max(indices_size).max(instances_size);
+        let limit = device.limits().max_storage_buffer_binding_size as u64;
+        if size > limit {
+            return Err(GrassConfigError::BufferSize { size, limit });
         }
 
-        let src_vertices = create_source(50, 50, 0.05);
-        let src_vertices_len = src_vertices.len();
         let src_vertices_buf = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
             label: Some("src_vertices"),
-            contents: bytemuck::cast_slice(&src_vertices),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(source.vertices.as_slice()),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST,
         });
 
-        let segments = 5;
-        let blades = 5;
-        let vertices_count = src_vertices_len * (segments * 2 + 1) * blades;
-        let blades_count = src_vertices_len * blades;
+        let trample_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("trample"),
+            size: (src_vertices_len * size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cut_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("cut"),
+            size: (src_vertices_len * size_of::<[f32; 2]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let dst_vertices_buf = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("dst_vertices"),
-            size: (vertices_count * size_of::<DstVertex>()) as wgpu::BufferAddress,
+            size: vertices_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+
+        let dst_instances_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("dst_instances"),
+            size: instances_size,
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
             mapped_at_creation: false,
         });
 
         let dst_vertices_len = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
-            label: Some("dst_vertices_count"),
+            label: Some("dst_counters"),
             contents: bytemuck::bytes_of(&[0u32; 4]),
-            usage: wgpu::BufferUsages::STORAGE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
         });
 
         let dst_indirect_buf = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
@@ -240,47 +745,92 @@ impl GrassData {
                 vertex_offset: 0,
                 base_instance: 0,
             }),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC,
         });
 
-        let indices = indices(blades_count as u32, segments as u32);
-        let indices_buf = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
-            label: Some("indices"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let indices_buf = if config.instanced {
+            // the strip of a single blade, shared by every instance
+            let indices: Vec<u32> = (0..config.vertices_per_blade()).collect();
+            device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+                label: Some("indices"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
+            })
+        } else {
+            // strips of every blade followed by a strip reset, written by the compute pass
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("indices"),
+                size: indices_size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDEX,
+                mapped_at_creation: false,
+            })
+        };
 
-        let config = GrassConfig::default();
+        let uniform = GrassUniform::new(src_vertices_len as u32, &config, &capacity);
         let params_buf = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
             label: Some("params"),
-            contents: bytemuck::bytes_of(&GrassUniform {
-                time: 123.456,
-                length: src_vertices_len as u32,
-
-                blades: config.blades,
-
-                blade_radius: config.blade_radius,
-                blade_forward: config.blade_forward,
-                blade_curve: config.blade_curve,
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
 
-                wind_speed: config.wind_speed,
-                wind_strength: config.wind_strength,
-            }),
+        let material = GrassMaterialUniform::new(&config.lighting);
+        let material_buf = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("grass_material"),
+            contents: bytemuck::bytes_of(&material),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        Self {
+        Ok(Self {
+            config,
+            source,
             params_buf,
+            material_buf,
+
+            trample_buf,
+            cut_buf,
 
             src_vertices_buf,
             src_vertices_len,
 
             vertex_buffer: dst_vertices_buf,
+            instance_buffer: dst_instances_buf,
             vertex_buffer_len: dst_vertices_len,
             indirect_buffer: dst_indirect_buf,
 
             index_buffer: indices_buf,
+            capacity,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    /// Copies the per-root trample and cut state of the buffers this field replaces,
+    /// as long as they were built for the same roots.
+    fn carry_state(&self, previous: &GrassData, device: &RenderDevice, queue: &RenderQueue) {
+        if !self.source.same_roots(&previous.source) {
+            return;
         }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("grass_carry_state"),
+        });
+        let roots = self.src_vertices_len as u64;
+        encoder.copy_buffer_to_buffer(
+            &previous.trample_buf,
+            0,
+            &self.trample_buf,
+            0,
+            roots * size_of::<[f32; 4]>() as u64,
+        );
+        encoder.copy_buffer_to_buffer(
+            &previous.cut_buf,
+            0,
+            &self.cut_buf,
+            0,
+            roots * size_of::<[f32; 2]>() as u64,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
     }
 }
 
@@ -299,12 +849,87 @@ pub struct GrassBindGroup {
     count: u32,
 }
 
+/// Compute bind groups of all grass fields dispatched this frame.
+#[derive(Default)]
+pub struct GrassBindGroups(pub Vec<GrassBindGroup>);
+
 fn queue_bind_group(
     mut commands: Commands,
     pipeline: Res<GrassComputePipeline>,
     device: Res<RenderDevice>,
-    data: Res<GrassData>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    views: Res<GrassViewsBuffer>,
+    interactors: Res<GrassInteractorsBuffer>,
+    cuts: Res<GrassCutsBuffer>,
+    pyramid: Res<GrassDepthPyramid>,
+    pyramid_pipeline: Res<GrassDepthPyramidPipeline>,
+    wind: Res<Wind>,
+    wind_buffer: Res<GrassWindBuffer>,
+    buffers: Res<GrassBuffers>,
 ) {
+    let shared = SharedBindings {
+        images: &images,
+        fallback_image: &fallback_image,
+        views: &views,
+        interactors: &interactors,
+        cuts: &cuts,
+        depth_pyramid: pyramid
+            .texture
+            .as_ref()
+            .map_or(&pyramid_pipeline.fallback, |texture| &texture.view),
+        wind: &wind,
+        wind_buffer: &wind_buffer,
+    };
+
+    let bind_groups = buffers
+        .0
+        .values()
+        .map(|data| create_bind_group(&device, &pipeline, &shared, data))
+        .collect();
+
+    commands.insert_resource(GrassBindGroups(bind_groups));
+}
+
+/// Resources bound by every field.
+struct SharedBindings<'a> {
+    images: &'a RenderAssets<Image>,
+    fallback_image: &'a FallbackImage,
+    views: &'a GrassViewsBuffer,
+    interactors: &'a GrassInteractorsBuffer,
+    cuts: &'a GrassCutsBuffer,
+    /// Depth pyramid of the previous frame, or a texel at the far plane that hides nothing.
+    depth_pyramid: &'a TextureView,
+    wind: &'a Wind,
+    wind_buffer: &'a GrassWindBuffer,
+}
+
+impl<'a> SharedBindings<'a> {
+    /// Resolves an optional texture, falling back to plain white while it is unset or still loading.
+    fn image_or_fallback(&self, handle: &Option<Handle<Image>>) -> (&'a TextureView, &'a Sampler) {
+        match handle.as_ref().and_then(|handle| self.images.get(handle)) {
+            Some(image) => (&image.texture_view, &image.sampler),
+            None => (
+                &self.fallback_image.texture_view,
+                &self.fallback_image.sampler,
+            ),
+        }
+    }
+}
+
+fn create_bind_group(
+    device: &RenderDevice,
+    pipeline: &GrassComputePipeline,
+    shared: &SharedBindings,
+    data: &GrassData,
+) -> GrassBindGroup {
+    let (density_map, density_sampler) = shared.image_or_fallback(&data.config.density_map);
+    // both tile in world space, the sampler of the image would clamp
+    let (wind_turbulence, _) = shared.image_or_fallback(&shared.wind.turbulence);
+    let (color_map, _) = shared.image_or_fallback(&data.config.color_map);
+    let (wind_sampler, color_sampler) = (&pipeline.repeat_sampler, &pipeline.repeat_sampler);
+    let (species_map, species_sampler) = shared.image_or_fallback(&data.config.species_map);
+
     let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: None,
         layout: &pipeline.compute_bind_group_layout,
@@ -329,10 +954,78 @@ fn queue_bind_group(
                 binding: 4,
                 resource: data.indirect_buffer.as_entire_binding(),
             },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::TextureView(density_map),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::Sampler(density_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: shared.views.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: data.index_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: shared.interactors.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: data.trample_buf.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: shared.wind_buffer.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: BindingResource::TextureView(wind_turbulence),
+            },
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: BindingResource::Sampler(wind_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: BindingResource::TextureView(color_map),
+            },
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: BindingResource::Sampler(color_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: shared.cuts.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 17,
+                resource: data.cut_buf.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 18,
+                resource: BindingResource::TextureView(species_map),
+            },
+            wgpu::BindGroupEntry {
+                binding: 19,
+                resource: BindingResource::Sampler(species_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 20,
+                resource: data.instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 21,
+                resource: BindingResource::TextureView(shared.depth_pyramid),
+            },
         ],
     });
 
     let count = data.src_vertices_len as u32;
     let count = count / WORKGROUPS + count % WORKGROUPS;
-    commands.insert_resource(GrassBindGroup { bind_group, count });
+    GrassBindGroup { bind_group, count }
 }
@@ -0,0 +1,130 @@
+use bevy::{
+    pbr::{MeshPipeline, MeshUniform},
+    prelude::*,
+    render::render_phase::{DrawFunctions, RenderPhase},
+    render::{render_resource::*, view::ExtractedView},
+};
+
+use super::render::{DrawGrass, GrassPipelineKey, GrassRenderPipeline};
+use super::{Grass, GrassBuffers};
+use crate::toon::normal_pass::{self, Normal3d, NormalPassMaterial};
+
+/// Adds the fields marked with `NormalPassMaterial` to the normal pass of every view.
+pub fn queue_grass_normals(
+    pipeline: Res<GrassNormalPipeline>,
+    draw_functions: Res<DrawFunctions<Normal3d>>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<GrassNormalPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    buffers: Res<GrassBuffers>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Normal3d>)>,
+    query: Query<(Entity, &MeshUniform), (With<Grass>, With<NormalPassMaterial>)>,
+) {
+    let draw_function = draw_functions.read().get_id::<DrawGrass>().unwrap();
+
+    for (view, mut phase) in &mut views {
+        let rangefinder = view.rangefinder3d();
+        for (entity, mesh_uniform) in &query {
+            let data = match buffers.0.get(&entity) {
+                Some(data) => data,
+                None => continue,
+            };
+            let key = GrassPipelineKey::from_msaa_samples(msaa.samples)
+                | GrassPipelineKey::from_config(&data.config);
+
+            phase.add(Normal3d {
+                entity,
+                pipeline: pipelines.specialize(&mut pipeline_cache, &pipeline, key),
+                draw_function,
+                distance: rangefinder.distance(&mesh_uniform.transform),
+            });
+        }
+    }
+}
+
+/// Draws the world space normals of the blades into the normal texture of the view.
+pub struct GrassNormalPipeline {
+    pub view_layout: BindGroupLayout,
+    pub mesh_layout: BindGroupLayout,
+    /// Shared with the main pass, `DrawGrass` binds it in both.
+    pub material_layout: BindGroupLayout,
+    pub shader: Handle<Shader>,
+}
+
+impl FromWorld for GrassNormalPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        let mesh_pipeline = world.resource::<MeshPipeline>();
+
+        let view_layout = mesh_pipeline.view_layout.clone();
+        let mesh_layout = mesh_pipeline.mesh_layout.clone();
+        let material_layout = world
+            .resource::<GrassRenderPipeline>()
+            .material_layout
+            .clone();
+        let shader = asset_server.load("shaders/grass_render.wgsl");
+
+        Self {
+            view_layout,
+            mesh_layout,
+            material_layout,
+            shader,
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for GrassNormalPipeline {
+    type Key = GrassPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let shader_defs = key.shader_defs();
+        let vb_desc = key.vertex_buffer_layout();
+
+        RenderPipelineDescriptor {
+            label: Some("grass normal pass".into()),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.mesh_layout.clone(),
+                self.material_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                entry_point: "vertex_normal_pass".into(),
+                shader_defs: shader_defs.clone(),
+                buffers: vec![vb_desc],
+            },
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: Some(wgpu::IndexFormat::Uint32),
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "fragment_normal_pass".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: normal_pass::FORMAT,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            // the blades are already in the depth buffer from the main pass
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: key.alpha_to_coverage(),
+            },
+        }
+    }
+}
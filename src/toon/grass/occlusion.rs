@@ -0,0 +1,378 @@
+use bevy::{
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{Node, NodeRunError, RenderGraphContext, SlotInfo, SlotType},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        view::{ExtractedView, ViewDepthTexture},
+    },
+};
+use std::borrow::Cow;
+
+use super::GrassViews;
+
+pub mod draw_grass_graph {
+    pub mod node {
+        /// Label for the depth pyramid node.
+        pub const DEPTH_PYRAMID: &str = "grass_depth_pyramid";
+    }
+}
+
+const FORMAT: TextureFormat = TextureFormat::R32Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Mip chain of the depth of the first active camera, each texel holding the farthest depth below it.
+///
+/// Built after the main pass and read by the grass compute pass of the next frame,
+/// together with the view projection it was rendered with.
+#[derive(Default)]
+pub struct GrassDepthPyramid {
+    /// Camera the pyramid is built for.
+    pub view: Option<Entity>,
+    pub texture: Option<DepthPyramidTexture>,
+    /// View projection of this frame, used once the pyramid is built.
+    pub view_proj: Mat4,
+    /// View projection the current content of the pyramid was rendered with.
+    pub previous_view_proj: Mat4,
+    /// The pyramid holds the depth of the previous frame.
+    pub ready: bool,
+    bind_groups: Option<DepthPyramidBindGroups>,
+}
+
+pub struct DepthPyramidTexture {
+    pub texture: Texture,
+    /// All the mips, read by the grass compute pass.
+    pub view: TextureView,
+    pub mips: Vec<TextureView>,
+    pub size: UVec2,
+}
+
+impl DepthPyramidTexture {
+    fn new(device: &RenderDevice, size: UVec2) -> Self {
+        let mip_count = 32 - size.x.max(size.y).leading_zeros();
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("grass_depth_pyramid"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: FORMAT,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let mips = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("grass_depth_pyramid_mip"),
+                    base_mip_level: mip,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..default()
+                })
+            })
+            .collect();
+
+        Self {
+            texture,
+            view,
+            mips,
+            size,
+        }
+    }
+}
+
+struct DepthPyramidBindGroups {
+    multisampled: bool,
+    copy: BindGroup,
+    /// Reads mip `i` and writes mip `i + 1`.
+    downsample: Vec<BindGroup>,
+}
+
+/// (Re)creates the pyramid for the first active camera and tracks whether it was built last frame.
+pub fn prepare_depth_pyramid(
+    mut pyramid: ResMut<GrassDepthPyramid>,
+    device: Res<RenderDevice>,
+    views: Res<GrassViews>,
+    cameras: Query<(&ExtractedCamera, &ExtractedView)>,
+) {
+    let pyramid = &mut *pyramid;
+
+    let camera = views
+        .entities
+        .first()
+        .and_then(|&entity| Some((entity, cameras.get(entity).ok()?)));
+    let (entity, (camera, view)) = match camera {
+        Some(camera) => camera,
+        None => {
+            *pyramid = GrassDepthPyramid::default();
+            return;
+        }
+    };
+    let size = match camera.physical_target_size {
+        Some(size) if size.x > 0 && size.y > 0 => size,
+        _ => {
+            *pyramid = GrassDepthPyramid::default();
+            return;
+        }
+    };
+
+    let rebuilt = pyramid.view != Some(entity)
+        || pyramid
+            .texture
+            .as_ref()
+            .map_or(true, |texture| texture.size != size);
+    if rebuilt {
+        pyramid.texture = Some(DepthPyramidTexture::new(&device, size));
+    }
+
+    pyramid.ready = !rebuilt;
+    pyramid.view = Some(entity);
+    pyramid.previous_view_proj = pyramid.view_proj;
+    pyramid.view_proj = view.projection * view.transform.compute_matrix().inverse();
+}
+
+pub fn queue_depth_pyramid_bind_groups(
+    mut pyramid: ResMut<GrassDepthPyramid>,
+    pipeline: Res<GrassDepthPyramidPipeline>,
+    device: Res<RenderDevice>,
+    msaa: Res<Msaa>,
+    depths: Query<&ViewDepthTexture>,
+) {
+    let pyramid = &mut *pyramid;
+    pyramid.bind_groups = None;
+
+    let (texture, depth) = match (&pyramid.texture, pyramid.view) {
+        (Some(texture), Some(view)) => match depths.get(view) {
+            Ok(depth) => (texture, depth),
+            Err(_) => return,
+        },
+        _ => return,
+    };
+
+    let multisampled = msaa.samples > 1;
+    let copy = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("grass_depth_pyramid_copy"),
+        layout: if multisampled {
+            &pipeline.copy_multisampled_layout
+        } else {
+            &pipeline.copy_layout
+        },
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&depth.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&texture.mips[0]),
+            },
+        ],
+    });
+
+    let downsample = texture
+        .mips
+        .windows(2)
+        .map(|mips| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("grass_depth_pyramid_downsample"),
+                layout: &pipeline.downsample_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&mips[0]),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&mips[1]),
+                    },
+                ],
+            })
+        })
+        .collect();
+
+    pyramid.bind_groups = Some(DepthPyramidBindGroups {
+        multisampled,
+        copy,
+        downsample,
+    });
+}
+
+pub struct GrassDepthPyramidPipeline {
+    pub copy_layout: BindGroupLayout,
+    pub copy_multisampled_layout: BindGroupLayout,
+    pub downsample_layout: BindGroupLayout,
+    /// Single texel at the far plane, bound while there is no pyramid, it hides nothing.
+    pub fallback: TextureView,
+    pub copy_pipeline: CachedComputePipelineId,
+    pub copy_multisampled_pipeline: CachedComputePipelineId,
+    pub downsample_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for GrassDepthPyramidPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let source_layout = |label, sample_type, multisampled| {
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: FORMAT,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                ],
+            })
+        };
+
+        let copy_layout = source_layout("grass_depth_copy", TextureSampleType::Depth, false);
+        let copy_multisampled_layout =
+            source_layout("grass_depth_copy", TextureSampleType::Depth, true);
+        let downsample_layout = source_layout(
+            "grass_depth_downsample",
+            TextureSampleType::Float { filterable: false },
+            false,
+        );
+
+        // new textures are zeroed, the far plane with reverse z
+        let fallback = device
+            .create_texture(&TextureDescriptor {
+                label: Some("grass_depth_pyramid_fallback"),
+                size: Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: FORMAT,
+                usage: TextureUsages::TEXTURE_BINDING,
+            })
+            .create_view(&TextureViewDescriptor::default());
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/grass_depth_pyramid.wgsl");
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue =
+            |layout: &BindGroupLayout, shader_defs: &[&str], entry_point: &'static str| {
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("grass_depth_pyramid".into()),
+                    layout: Some(vec![layout.clone()]),
+                    shader: shader.clone(),
+                    shader_defs: shader_defs.iter().map(|def| def.to_string()).collect(),
+                    entry_point: Cow::from(entry_point),
+                })
+            };
+
+        let copy_pipeline = queue(&copy_layout, &[], "copy");
+        let copy_multisampled_pipeline =
+            queue(&copy_multisampled_layout, &["MULTISAMPLED"], "copy");
+        let downsample_pipeline = queue(&downsample_layout, &["DOWNSAMPLE"], "downsample");
+
+        Self {
+            copy_layout,
+            copy_multisampled_layout,
+            downsample_layout,
+            fallback,
+            copy_pipeline,
+            copy_multisampled_pipeline,
+            downsample_pipeline,
+        }
+    }
+}
+
+/// Builds the depth pyramid right after the main pass of the camera it belongs to.
+#[derive(Default)]
+pub struct GrassDepthPyramidNode;
+
+impl GrassDepthPyramidNode {
+    pub const IN_VIEW: &'static str = "view";
+}
+
+impl Node for GrassDepthPyramidNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let pyramid = world.resource::<GrassDepthPyramid>();
+        if pyramid.view != Some(view_entity) {
+            return Ok(());
+        }
+
+        let (texture, bind_groups) = match (&pyramid.texture, &pyramid.bind_groups) {
+            (Some(texture), Some(bind_groups)) => (texture, bind_groups),
+            _ => return Ok(()),
+        };
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<GrassDepthPyramidPipeline>();
+
+        let copy_pipeline = if bind_groups.multisampled {
+            pipeline.copy_multisampled_pipeline
+        } else {
+            pipeline.copy_pipeline
+        };
+        let copy_pipeline = pipeline_cache.get_compute_pipeline(copy_pipeline);
+        let downsample_pipeline = pipeline_cache.get_compute_pipeline(pipeline.downsample_pipeline);
+        let (copy_pipeline, downsample_pipeline) = match (copy_pipeline, downsample_pipeline) {
+            (Some(copy), Some(downsample)) => (copy, downsample),
+            _ => return Ok(()),
+        };
+
+        let workgroups = |mip: usize| {
+            let size = UVec2::new(texture.size.x >> mip, texture.size.y >> mip).max(UVec2::ONE);
+            (size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE
+        };
+
+        let mut pass = render_context
+            .command_encoder
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("grass_depth_pyramid"),
+            });
+
+        pass.set_pipeline(copy_pipeline);
+        pass.set_bind_group(0, &bind_groups.copy, &[]);
+        let count = workgroups(0);
+        pass.dispatch_workgroups(count.x, count.y, 1);
+
+        pass.set_pipeline(downsample_pipeline);
+        for (mip, bind_group) in bind_groups.downsample.iter().enumerate() {
+            pass.set_bind_group(0, bind_group, &[]);
+            let count = workgroups(mip + 1);
+            pass.dispatch_workgroups(count.x, count.y, 1);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph,
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+    },
+    utils::{HashMap, HashSet},
+};
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{DrawIndexedIndirect, GrassBuffers, GrassCapacity};
+
+/// Totals over all fields of the latest frame read back, shared by the main and the render world.
+#[derive(Clone, Default)]
+pub struct GrassStats(pub Arc<Mutex<Option<GrassFrameStats>>>);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GrassFrameStats {
+    pub blades: u64,
+    pub vertices: u64,
+    pub indices: u64,
+    /// Blades that did not fit into the output buffers.
+    pub dropped: u64,
+}
+
+/// Capacity the fields that dropped blades need, grown into by `prepare_grass_buffers`.
+#[derive(Default)]
+pub struct GrassOverflows(pub HashMap<Entity, GrassOverflow>);
+
+#[derive(Debug, Clone, Copy)]
+pub struct GrassOverflow {
+    /// Generation of the buffers the blades were dropped from.
+    pub generation: u64,
+    /// Reserved by the blades of the field, past the capacity of those buffers.
+    pub required: GrassCapacity,
+}
+
+/// Frames the counters can be in flight for before a frame is skipped.
+const SLOTS: usize = 3;
+
+/// Counters followed by the indirect draw of a field, padded to the copy alignment.
+const STRIDE: u64 = {
+    let size = (size_of::<[u32; 4]>() + size_of::<DrawIndexedIndirect>()) as u64;
+    let align = wgpu::COPY_BUFFER_ALIGNMENT;
+    (size + align - 1) / align * align
+};
+
+struct ReadbackField {
+    entity: Entity,
+    instanced: bool,
+    grow_buffers: bool,
+    capacity: GrassCapacity,
+    generation: u64,
+}
+
+struct ReadbackSlot {
+    buffer: Buffer,
+    size: u64,
+    /// Fields copied into the buffer, in order.
+    fields: Vec<ReadbackField>,
+    mapped: Arc<AtomicBool>,
+    in_flight: bool,
+}
+
+impl ReadbackSlot {
+    fn new(device: &RenderDevice, size: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grass_readback"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            size,
+            fields: Vec::new(),
+            mapped: Arc::default(),
+            in_flight: false,
+        }
+    }
+
+    /// Bytes of the fields copied this frame.
+    fn mapped_size(&self) -> u64 {
+        self.fields.len() as u64 * STRIDE
+    }
+}
+
+/// Staging buffers the counters of the compute pass are copied into and mapped from.
+#[derive(Default)]
+pub struct GrassReadback {
+    slots: Vec<ReadbackSlot>,
+    /// Slot written this frame.
+    current: Option<usize>,
+    overflowing: HashSet<Entity>,
+}
+
+/// Collects the slots mapped since the last frame, before the buffers of the fields are refreshed.
+///
+/// Fields that dropped blades are reported once until they recover,
+/// the ones that grow get the capacity they asked for on the next refresh of their buffers.
+pub fn prepare_grass_readback(
+    mut readback: ResMut<GrassReadback>,
+    mut overflows: ResMut<GrassOverflows>,
+    stats: Option<Res<GrassStats>>,
+    device: Res<RenderDevice>,
+    buffers: Res<GrassBuffers>,
+) {
+    let readback = &mut *readback;
+    if readback.slots.iter().any(|slot| slot.in_flight) {
+        device.wgpu_device().poll(wgpu::Maintain::Poll);
+    }
+
+    for slot in &mut readback.slots {
+        if !slot.in_flight || !slot.mapped.load(Ordering::Acquire) {
+            continue;
+        }
+
+        let mut frame = GrassFrameStats::default();
+        {
+            let data = slot.buffer.slice(..slot.mapped_size()).get_mapped_range();
+            for (field, chunk) in slot.fields.iter().zip(data.chunks_exact(STRIDE as usize)) {
+                let counters: &[u32] = bytemuck::cast_slice(chunk);
+                // reserved by the blades, may run past the capacity
+                let required = GrassCapacity {
+                    vertices: counters[0] as u64,
+                    blades: counters[1] as u64,
+                    indices: counters[2] as u64,
+                };
+                let dropped = counters[3] as u64;
+                let (index_count, instance_count) = (counters[4] as u64, counters[5] as u64);
+
+                // the instanced mode only writes blades, every index is a vertex of the vertex shader
+                let indices = index_count * instance_count;
+                let vertices = if field.instanced {
+                    indices
+                } else {
+                    required.vertices.min(field.capacity.vertices)
+                };
+
+                if dropped == 0 {
+                    readback.overflowing.remove(&field.entity);
+                } else if readback.overflowing.insert(field.entity) {
+                    warn!(
+                        "grass field {:?} dropped {} of {} blades, its output buffers are too small",
+                        field.entity, dropped, required.blades,
+                    );
+                }
+                if dropped != 0 && field.grow_buffers {
+                    let overflow = GrassOverflow {
+                        generation: field.generation,
+                        required,
+                    };
+                    overflows.0.insert(field.entity, overflow);
+                }
+
+                frame.blades += required.blades - dropped;
+                frame.vertices += vertices;
+                frame.indices += indices;
+                frame.dropped += dropped;
+            }
+        }
+        slot.buffer.unmap();
+        slot.in_flight = false;
+        slot.mapped.store(false, Ordering::Release);
+
+        if let Some(stats) = &stats {
+            *stats.0.lock().unwrap() = Some(frame);
+        }
+    }
+
+    readback
+        .overflowing
+        .retain(|entity| buffers.0.contains_key(entity));
+}
+
+/// Picks a free slot for this frame and lists the fields copied into it,
+/// after `prepare_grass_buffers` so every listed field has the buffers the compute pass fills.
+///
+/// Only runs for the fields that grow their buffers, or for every field with `GrassDiagnosticsPlugin`.
+pub fn prepare_grass_readback_slot(
+    mut readback: ResMut<GrassReadback>,
+    stats: Option<Res<GrassStats>>,
+    device: Res<RenderDevice>,
+    buffers: Res<GrassBuffers>,
+) {
+    readback.current = None;
+
+    let fields: Vec<_> = buffers
+        .0
+        .iter()
+        .filter(|(_, data)| stats.is_some() || data.config.grow_buffers)
+        .map(|(entity, data)| ReadbackField {
+            entity: *entity,
+            instanced: data.config.instanced,
+            grow_buffers: data.config.grow_buffers,
+            capacity: data.capacity,
+            generation: data.generation,
+        })
+        .collect();
+    if fields.is_empty() {
+        return;
+    }
+    let size = fields.len() as u64 * STRIDE;
+
+    let index = match readback.slots.iter().position(|slot| !slot.in_flight) {
+        Some(index) => index,
+        None if readback.slots.len() < SLOTS => {
+            readback.slots.push(ReadbackSlot::new(&device, size));
+            readback.slots.len() - 1
+        }
+        // every slot is still waiting on the gpu, skip this frame
+        None => return,
+    };
+
+    let slot = &mut readback.slots[index];
+    if slot.size < size {
+        *slot = ReadbackSlot::new(&device, size.next_power_of_two());
+    }
+    slot.fields = fields;
+    readback.current = Some(index);
+}
+
+/// Maps the slot written this frame, once the frame has been submitted.
+pub fn map_grass_readback(mut readback: ResMut<GrassReadback>) {
+    let index = match readback.current.take() {
+        Some(index) => index,
+        None => return,
+    };
+
+    let slot = &mut readback.slots[index];
+    let mapped = slot.mapped.clone();
+    slot.buffer
+        .slice(..slot.mapped_size())
+        .map_async(wgpu::MapMode::Read, move |result| {
+            if result.is_ok() {
+                mapped.store(true, Ordering::Release);
+            }
+        });
+    slot.in_flight = true;
+}
+
+/// Copies the counters of every field into the slot of this frame, after the compute pass.
+#[derive(Default)]
+pub struct GrassReadbackNode {}
+
+impl render_graph::Node for GrassReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let readback = world.resource::<GrassReadback>();
+        let buffers = world.resource::<GrassBuffers>();
+
+        let slot = match readback.current {
+            Some(index) => &readback.slots[index],
+            None => return Ok(()),
+        };
+
+        for (index, field) in slot.fields.iter().enumerate() {
+            let data = match buffers.0.get(&field.entity) {
+                Some(data) => data,
+                None => continue,
+            };
+
+            let offset = index as u64 * STRIDE;
+            let counters = size_of::<[u32; 4]>() as u64;
+            let indirect = size_of::<DrawIndexedIndirect>() as u64;
+            let encoder = &mut context.command_encoder;
+            encoder.copy_buffer_to_buffer(
+                &data.vertex_buffer_len,
+                0,
+                &slot.buffer,
+                offset,
+                counters,
+            );
+            encoder.copy_buffer_to_buffer(
+                &data.indirect_buffer,
+                0,
+                &slot.buffer,
+                offset + counters,
+                indirect,
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -8,22 +8,50 @@ use bevy::{
         TrackedRenderPass,
     },
     render::texture::BevyDefault,
-    render::{render_resource::*, Extract},
+    render::{render_resource::*, renderer::RenderDevice, Extract},
 };
 
-use super::{Grass, GrassData};
+use std::mem::size_of;
+
+use super::material::{SetGrassMaterialBindGroup, MATERIAL_LAYOUT};
+use super::{BladeInstance, DstVertex, Grass, GrassBuffers, GrassConfig, GrassSource};
 
 pub fn extract_grass(
     mut commands: Commands,
-    query: Extract<Query<(Entity, &GlobalTransform), With<Grass>>>,
+    query: Extract<
+        Query<
+            (
+                Entity,
+                &GlobalTransform,
+                &GrassConfig,
+                ChangeTrackers<GrassConfig>,
+                Option<(&GrassSource, ChangeTrackers<GrassSource>)>,
+            ),
+            With<Grass>,
+        >,
+    >,
 ) {
-    for (entity, transform) in query.iter() {
+    for (entity, transform, config, config_tracker, source) in query.iter() {
         let transform = transform.compute_matrix();
-        commands.get_or_spawn(entity).insert(MeshUniform {
+        let mut entity = commands.get_or_spawn(entity);
+        entity.insert(MeshUniform {
             flags: 1, // SHADOW_RECEIVER
             transform,
             inverse_transpose_model: transform.inverse().transpose(),
         });
+
+        // only send the config and roots over when they were touched,
+        // a new source brings the config along as the one sent before it may have been dropped
+        let source_changed = match source {
+            Some((source, source_tracker)) if source_tracker.is_changed() => {
+                entity.insert(source.clone());
+                true
+            }
+            _ => false,
+        };
+        if config_tracker.is_changed() || source_changed {
+            entity.insert(config.clone());
+        }
     }
 }
 
@@ -33,6 +61,7 @@ pub fn queue_grass(
     msaa: Res<Msaa>,
     mut pipelines: ResMut<SpecializedRenderPipelines<GrassRenderPipeline>>,
     mut pipeline_cache: ResMut<PipelineCache>,
+    buffers: Res<GrassBuffers>,
     mut view_query: Query<&mut RenderPhase<Opaque3d>>,
     query: Query<Entity, With<Grass>>,
 ) {
@@ -40,7 +69,12 @@ pub fn queue_grass(
 
     for mut opaque_phase in view_query.iter_mut() {
         for entity in query.iter() {
-            let key = GrassPipelineKey::from_msaa_samples(msaa.samples);
+            let data = match buffers.0.get(&entity) {
+                Some(data) => data,
+                None => continue,
+            };
+            let key = GrassPipelineKey::from_msaa_samples(msaa.samples)
+                | GrassPipelineKey::from_config(&data.config);
 
             let pipeline = pipelines.specialize(&mut pipeline_cache, &pipeline, key);
 
@@ -58,23 +92,31 @@ pub type DrawGrass = (
     SetItemPipeline,
     SetMeshViewBindGroup<0>,
     SetMeshBindGroup<1>,
+    SetGrassMaterialBindGroup<2>,
     DrawGrassCommand,
 );
 
 pub struct DrawGrassCommand;
 
 impl EntityRenderCommand for DrawGrassCommand {
-    type Param = SRes<GrassData>;
+    type Param = SRes<GrassBuffers>;
 
     #[inline]
     fn render<'w>(
         _view: Entity,
-        _item: Entity,
+        item: Entity,
         query: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let data = query.into_inner();
-        pass.set_vertex_buffer(0, data.vertex_buffer.slice(..));
+        let data = match query.into_inner().0.get(&item) {
+            Some(data) => data,
+            None => return RenderCommandResult::Failure,
+        };
+        if data.config.instanced {
+            pass.set_vertex_buffer(0, data.instance_buffer.slice(..));
+        } else {
+            pass.set_vertex_buffer(0, data.vertex_buffer.slice(..));
+        }
         pass.set_index_buffer(data.index_buffer.slice(..), 0, wgpu::IndexFormat::Uint32);
         pass.draw_indexed_indirect(&data.indirect_buffer, 0);
         RenderCommandResult::Success
@@ -85,6 +127,11 @@ bitflags::bitflags! {
     #[repr(transparent)]
     pub struct GrassPipelineKey: u32 {
         const NONE = 0;
+        const INSTANCED = 1 << 0;
+        /// Soft edges and the distance dissolve, through alpha to coverage under MSAA.
+        const FADE = 1 << 1;
+        /// Cel shaded lighting, see `GrassLighting::toon`.
+        const TOON = 1 << 2;
         const MSAA_RESERVED_BITS = Self::MSAA_MASK_BITS << Self::MSAA_SHIFT_BITS;
     }
 }
@@ -101,11 +148,78 @@ impl GrassPipelineKey {
     pub fn msaa_samples(&self) -> u32 {
         ((self.bits >> Self::MSAA_SHIFT_BITS) & Self::MSAA_MASK_BITS) + 1
     }
+
+    pub fn from_config(config: &GrassConfig) -> Self {
+        let mut key = Self::NONE;
+        if config.instanced {
+            key |= Self::INSTANCED;
+        }
+        if config.fade {
+            key |= Self::FADE;
+        }
+        if config.lighting.toon {
+            key |= Self::TOON;
+        }
+        key
+    }
+
+    pub fn alpha_to_coverage(&self) -> bool {
+        self.contains(Self::FADE) && self.msaa_samples() > 1
+    }
+
+    pub fn shader_defs(&self) -> Vec<String> {
+        let mut shader_defs = vec![String::from("VERTEX_UVS")];
+        if self.contains(Self::INSTANCED) {
+            shader_defs.push(String::from("INSTANCED"));
+        }
+        if self.contains(Self::FADE) {
+            shader_defs.push(String::from("FADE"));
+        }
+        if self.alpha_to_coverage() {
+            shader_defs.push(String::from("ALPHA_TO_COVERAGE"));
+        }
+        if self.contains(Self::TOON) {
+            shader_defs.push(String::from("TOON"));
+        }
+        shader_defs
+    }
+
+    /// Vertices written by the compute pass, or one blade per instance.
+    pub fn vertex_buffer_layout(&self) -> VertexBufferLayout {
+        if self.contains(Self::INSTANCED) {
+            let layout = VertexBufferLayout::from_vertex_formats(
+                wgpu::VertexStepMode::Instance,
+                [
+                    wgpu::VertexFormat::Float32x4,
+                    wgpu::VertexFormat::Float32x4,
+                    wgpu::VertexFormat::Float32x4,
+                    wgpu::VertexFormat::Float32x4,
+                    wgpu::VertexFormat::Uint32x4,
+                ],
+            );
+            debug_assert_eq!(layout.array_stride, size_of::<BladeInstance>() as u64);
+            layout
+        } else {
+            let layout = VertexBufferLayout::from_vertex_formats(
+                wgpu::VertexStepMode::Vertex,
+                [
+                    wgpu::VertexFormat::Float32x3,
+                    wgpu::VertexFormat::Float32x3,
+                    wgpu::VertexFormat::Float32x3,
+                    wgpu::VertexFormat::Float32x2,
+                    wgpu::VertexFormat::Unorm8x4,
+                ],
+            );
+            debug_assert_eq!(layout.array_stride, size_of::<DstVertex>() as u64);
+            layout
+        }
+    }
 }
 
 pub struct GrassRenderPipeline {
     pub view_layout: BindGroupLayout,
     pub mesh_layout: BindGroupLayout,
+    pub material_layout: BindGroupLayout,
     pub shader: Handle<Shader>,
 }
 
@@ -116,11 +230,15 @@ impl FromWorld for GrassRenderPipeline {
 
         let view_layout = mesh_pipeline.view_layout.clone();
         let mesh_layout = mesh_pipeline.mesh_layout.clone();
+        let material_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&MATERIAL_LAYOUT);
         let shader = asset_server.load("shaders/grass_render.wgsl");
 
         Self {
             view_layout,
             mesh_layout,
+            material_layout,
             shader,
         }
     }
@@ -130,21 +248,16 @@ impl SpecializedRenderPipeline for GrassRenderPipeline {
     type Key = GrassPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        //let shader_defs = key.shader_defs();
-        let shader_defs = vec![String::from("VERTEX_UVS")];
-
-        let vb_desc = VertexBufferLayout::from_vertex_formats(
-            wgpu::VertexStepMode::Vertex,
-            [
-                wgpu::VertexFormat::Float32x3,
-                wgpu::VertexFormat::Float32x3,
-                wgpu::VertexFormat::Float32x2,
-            ],
-        );
+        let shader_defs = key.shader_defs();
+        let vb_desc = key.vertex_buffer_layout();
 
         RenderPipelineDescriptor {
             label: None,
-            layout: Some(vec![self.view_layout.clone(), self.mesh_layout.clone()]),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.mesh_layout.clone(),
+                self.material_layout.clone(),
+            ]),
             vertex: VertexState {
                 shader: self.shader.clone(),
                 entry_point: "vertex".into(),
@@ -189,8 +302,52 @@ impl SpecializedRenderPipeline for GrassRenderPipeline {
             multisample: MultisampleState {
                 count: key.msaa_samples(),
                 mask: !0,
-                alpha_to_coverage_enabled: false,
+                alpha_to_coverage_enabled: key.alpha_to_coverage(),
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::MainWorld;
+    use std::sync::Arc;
+
+    #[test]
+    fn config_is_sent_again_with_a_late_source() {
+        let mut render_world = World::new();
+        render_world.insert_resource(MainWorld::default());
+        let mut extract = SystemStage::single_threaded().with_system(extract_grass);
+
+        let config = GrassConfig {
+            seed: 7,
+            ..default()
+        };
+        let entity = render_world
+            .resource_mut::<MainWorld>()
+            .spawn()
+            .insert_bundle((Grass, GlobalTransform::default(), config))
+            .id();
+
+        // the surface is still being scattered
+        extract.run(&mut render_world);
+        assert!(render_world.get::<GrassConfig>(entity).is_some());
+        assert!(render_world.get::<GrassSource>(entity).is_none());
+        render_world.clear_entities();
+
+        render_world
+            .resource_mut::<MainWorld>()
+            .entity_mut(entity)
+            .insert(GrassSource {
+                vertices: Arc::new(Vec::new()),
+                seed: 7,
+            });
+        extract.run(&mut render_world);
+        assert!(render_world.get::<GrassSource>(entity).is_some());
+        let seed = render_world
+            .get::<GrassConfig>(entity)
+            .map(|config| config.seed);
+        assert_eq!(seed, Some(7));
+    }
+}
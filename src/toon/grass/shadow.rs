@@ -0,0 +1,122 @@
+use bevy::{
+    pbr::{SetMeshBindGroup, SetShadowViewBindGroup, Shadow, ShadowPipeline, SHADOW_FORMAT},
+    prelude::*,
+    render::render_phase::{DrawFunctions, RenderPhase, SetItemPipeline},
+    render::render_resource::*,
+};
+
+use super::render::{DrawGrassCommand, GrassPipelineKey};
+use super::{Grass, GrassBuffers};
+
+/// Adds the fields that cast shadows to the shadow phase of every light view.
+///
+/// The blades are the ones filled for the cameras, the culling of these fields also keeps
+/// the roots inside the frustum of any shadow casting light, so grass out of sight still casts its shadow.
+/// Roots beyond the last lod band are not drawn at all and cast none.
+pub fn queue_grass_shadows(
+    pipeline: Res<GrassShadowPipeline>,
+    draw_functions: Res<DrawFunctions<Shadow>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<GrassShadowPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    buffers: Res<GrassBuffers>,
+    mut view_query: Query<&mut RenderPhase<Shadow>>,
+    query: Query<Entity, With<Grass>>,
+) {
+    let draw_function = draw_functions.read().get_id::<DrawGrassShadow>().unwrap();
+
+    for mut shadow_phase in view_query.iter_mut() {
+        for entity in query.iter() {
+            let data = match buffers.0.get(&entity) {
+                Some(data) if data.config.cast_shadows => data,
+                _ => continue,
+            };
+            // depth only, the fade and the lighting have no say in it
+            let key = GrassPipelineKey::from_config(&data.config) & GrassPipelineKey::INSTANCED;
+
+            shadow_phase.add(Shadow {
+                distance: 0.0,
+                entity,
+                pipeline: pipelines.specialize(&mut pipeline_cache, &pipeline, key),
+                draw_function,
+            });
+        }
+    }
+}
+
+pub type DrawGrassShadow = (
+    SetItemPipeline,
+    SetShadowViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawGrassCommand,
+);
+
+/// Depth only pipeline drawing the blades filled by the compute pass into the shadow maps.
+pub struct GrassShadowPipeline {
+    pub view_layout: BindGroupLayout,
+    pub mesh_layout: BindGroupLayout,
+    pub shader: Handle<Shader>,
+}
+
+impl FromWorld for GrassShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        let shadow_pipeline = world.resource::<ShadowPipeline>();
+
+        let view_layout = shadow_pipeline.view_layout.clone();
+        let mesh_layout = shadow_pipeline.mesh_layout.clone();
+        let shader = asset_server.load("shaders/grass_shadow.wgsl");
+
+        Self {
+            view_layout,
+            mesh_layout,
+            shader,
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for GrassShadowPipeline {
+    type Key = GrassPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let shader_defs = key.shader_defs();
+        let vb_desc = key.vertex_buffer_layout();
+
+        RenderPipelineDescriptor {
+            label: Some("grass_shadow_pipeline".into()),
+            layout: Some(vec![self.view_layout.clone(), self.mesh_layout.clone()]),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                entry_point: "vertex".into(),
+                shader_defs,
+                buffers: vec![vb_desc],
+            },
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: Some(wgpu::IndexFormat::Uint32),
+            },
+            fragment: None,
+            depth_stencil: Some(DepthStencilState {
+                format: SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState {
+                    front: StencilFaceState::IGNORE,
+                    back: StencilFaceState::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+                bias: DepthBiasState {
+                    constant: 0,
+                    slope_scale: 0.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{PrimitiveTopology, VertexAttributeValues},
+    utils::HashSet,
+};
+use std::sync::Arc;
+
+use super::GrassConfig;
+
+/// Surface the roots of a grass field are scattered over.
+///
+/// The mesh is read in the local space of the grass entity.
+#[derive(Clone, Component)]
+pub struct GrassSurface {
+    pub mesh: Handle<Mesh>,
+
+    /// Roots per square unit of surface area.
+    pub density: f32,
+}
+
+impl Default for GrassSurface {
+    fn default() -> Self {
+        Self {
+            mesh: Handle::default(),
+            density: 400.0,
+        }
+    }
+}
+
+/// Roots scattered over a `GrassSurface`, rebuilt whenever the surface, its mesh or the seed changes.
+#[derive(Clone, Component)]
+pub struct GrassSource {
+    pub vertices: Arc<Vec<GrassSourceVertex>>,
+    /// Seed the roots were scattered with.
+    pub seed: u32,
+}
+
+impl GrassSource {
+    /// Both sources hold the same roots in the same order, so per-root state carries over.
+    pub fn same_roots(&self, other: &GrassSource) -> bool {
+        Arc::ptr_eq(&self.vertices, &other.vertices)
+            || bytemuck::cast_slice::<_, u8>(self.vertices.as_slice())
+                == bytemuck::cast_slice::<_, u8>(other.vertices.as_slice())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GrassSourceVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+pub fn scatter_grass(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    query: Query<(
+        Entity,
+        &GrassSurface,
+        Option<&GrassConfig>,
+        Option<&GrassSource>,
+        ChangeTrackers<GrassSurface>,
+    )>,
+) {
+    let mut modified = HashSet::default();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                modified.insert(handle.clone_weak());
+            }
+            AssetEvent::Removed { .. } => (),
+        }
+    }
+
+    for (entity, surface, config, source, surface_tracker) in query.iter() {
+        let seed = config.map_or(0, |config| config.seed);
+        let outdated = source.map_or(true, |source| source.seed != seed)
+            || surface_tracker.is_changed()
+            || modified.contains(&surface.mesh);
+
+        if !outdated {
+            continue;
+        }
+
+        // the mesh may still be loading, try again next frame
+        if let Some(mesh) = meshes.get(&surface.mesh) {
+            let vertices = scatter(mesh, surface.density, seed as u64);
+            commands.entity(entity).insert(GrassSource {
+                vertices: Arc::new(vertices),
+                seed,
+            });
+        }
+    }
+}
+
+/// Scatters roots over the triangles of `mesh`, area-weighted,
+/// taking the interpolated vertex normal or the face normal if the mesh has none.
+/// Uvs are interpolated as well, so density and other maps follow the mesh unwrap.
+pub fn scatter(mesh: &Mesh, density: f32, seed: u64) -> Vec<GrassSourceVertex> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        warn!("grass surface must be a triangle list");
+        return Vec::new();
+    }
+
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => positions,
+        _ => return Vec::new(),
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+        Some(VertexAttributeValues::Float32x3(normals)) => Some(normals),
+        _ => None,
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+        _ => None,
+    };
+
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+
+    let triangles: Vec<[usize; 3]> = indices
+        .chunks_exact(3)
+        .map(|tri| [tri[0], tri[1], tri[2]])
+        .collect();
+
+    // running sum of triangle areas to pick triangles proportionally to their area
+    let mut total_area = 0.0;
+    let cumulative_area: Vec<f32> = triangles
+        .iter()
+        .map(|&[a, b, c]| {
+            let (a, b, c) = (
+                Vec3::from(positions[a]),
+                Vec3::from(positions[b]),
+                Vec3::from(positions[c]),
+            );
+            total_area += (b - a).cross(c - a).length() * 0.5;
+            total_area
+        })
+        .collect();
+
+    if triangles.is_empty() || total_area <= 0.0 {
+        return Vec::new();
+    }
+
+    let count = (total_area * density).round() as usize;
+    let mut rng = Rng(seed);
+
+    (0..count)
+        .map(|_| {
+            let target = rng.next_f32() * total_area;
+            let index = cumulative_area
+                .partition_point(|&area| area < target)
+                .min(triangles.len() - 1);
+            let [i0, i1, i2] = triangles[index];
+
+            // uniformly distributed barycentric coordinates
+            let r1 = rng.next_f32().sqrt();
+            let r2 = rng.next_f32();
+            let (u, v, w) = (1.0 - r1, r1 * (1.0 - r2), r1 * r2);
+
+            let (a, b, c) = (
+                Vec3::from(positions[i0]),
+                Vec3::from(positions[i1]),
+                Vec3::from(positions[i2]),
+            );
+            let position = a * u + b * v + c * w;
+
+            let face_normal = (b - a).cross(c - a).normalize_or_zero();
+            let normal = normals
+                .map(|normals| {
+                    Vec3::from(normals[i0]) * u
+                        + Vec3::from(normals[i1]) * v
+                        + Vec3::from(normals[i2]) * w
+                })
+                .map(Vec3::normalize_or_zero)
+                .filter(|normal| *normal != Vec3::ZERO)
+                .unwrap_or(face_normal);
+
+            let uv = uvs
+                .map(|uvs| {
+                    Vec2::from(uvs[i0]) * u + Vec2::from(uvs[i1]) * v + Vec2::from(uvs[i2]) * w
+                })
+                .unwrap_or(Vec2::ZERO);
+
+            GrassSourceVertex {
+                position: position.into(),
+                normal: normal.into(),
+                uv: uv.into(),
+            }
+        })
+        .collect()
+}
+
+/// Small deterministic generator (splitmix64),
+/// so a surface always scatters to the same roots.
+pub(super) struct Rng(pub u64);
+
+impl Rng {
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u32 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(seed: u64) -> Vec<[f32; 8]> {
+        let mesh = Mesh::from(shape::Plane { size: 4.0 });
+        scatter(&mesh, 10.0, seed)
+            .iter()
+            .map(|vertex| bytemuck::cast(*vertex))
+            .collect()
+    }
+
+    #[test]
+    fn scatter_is_deterministic() {
+        assert_eq!(roots(7).len(), 160);
+        assert_eq!(roots(7), roots(7));
+        assert_ne!(roots(7), roots(8));
+    }
+}
@@ -0,0 +1,293 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::sync::Arc;
+
+use super::source::Rng;
+use super::{Grass, GrassConfig, GrassSource, GrassSourceVertex};
+
+/// Endless grass in square tiles around the active 3d camera.
+///
+/// Every tile is a grass field of its own, spawned with the `GrassConfig` of this entity,
+/// its blades and segments capped to the most detailed lod band the tile can reach,
+/// so only the tiles next to the camera are sized for full detail.
+/// Tiles beyond the last lod band are not spawned.
+/// Tiles leaving the ring are moved to the newly entered spots and rescattered,
+/// the roots only depend on the tile coordinates, so a spot always grows the same grass.
+///
+/// The tiles follow the world xz grid, only the height of this entity is used,
+/// its x and z translation, rotation and scale are ignored.
+#[derive(Clone, Component)]
+pub struct GrassStreaming {
+    /// Edge length of a tile in world units.
+    pub tile_size: f32,
+    /// Tiles kept in every direction from the one under the camera.
+    pub radius: i32,
+    /// Roots per square unit.
+    pub density: f32,
+    /// World space ground height at a point of the xz plane,
+    /// the plane at the height of this entity if unset.
+    pub height: Option<Arc<dyn Fn(Vec2) -> f32 + Send + Sync>>,
+}
+
+impl Default for GrassStreaming {
+    fn default() -> Self {
+        Self {
+            tile_size: 8.0,
+            radius: 3,
+            density: 20.0,
+            height: None,
+        }
+    }
+}
+
+/// Tile of a `GrassStreaming` entity.
+#[derive(Clone, Copy, Component)]
+pub struct GrassTile {
+    pub stream: Entity,
+    pub coord: IVec2,
+}
+
+pub fn stream_grass_tiles(
+    mut commands: Commands,
+    cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    streams: Query<(
+        Entity,
+        &GrassStreaming,
+        &GrassConfig,
+        &GlobalTransform,
+        ChangeTrackers<GrassStreaming>,
+        ChangeTrackers<GrassConfig>,
+    )>,
+    mut tiles: Query<(
+        Entity,
+        &mut GrassTile,
+        &mut Transform,
+        &mut GrassSource,
+        &GrassConfig,
+    )>,
+) {
+    // tiles of removed streams
+    for (entity, tile, ..) in tiles.iter() {
+        if !streams.contains(tile.stream) {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let camera = cameras
+        .iter()
+        .find(|(camera, _)| camera.is_active)
+        .map(|(_, transform)| transform.translation());
+    let camera = match camera {
+        Some(camera) => camera,
+        None => return,
+    };
+
+    for (stream_entity, stream, config, transform, stream_tracker, config_tracker) in streams.iter()
+    {
+        if stream.tile_size <= 0.0 || stream.radius < 0 {
+            continue;
+        }
+
+        let center = (Vec2::new(camera.x, camera.z) / stream.tile_size)
+            .floor()
+            .as_ivec2();
+        let rings: Vec<Option<GrassConfig>> = (0..=stream.radius)
+            .map(|ring| tile_config(config, stream.tile_size, ring))
+            .collect();
+        let wanted: HashMap<IVec2, &GrassConfig> = (-stream.radius..=stream.radius)
+            .flat_map(|y| (-stream.radius..=stream.radius).map(move |x| IVec2::new(x, y)))
+            .filter_map(|offset| {
+                let ring = offset.x.abs().max(offset.y.abs());
+                let config = rings[ring as usize].as_ref()?;
+                Some((center + offset, config))
+            })
+            .collect();
+
+        // a changed stream or seed invalidates the roots of every tile
+        let mut loaded = HashSet::default();
+        let mut free = Vec::new();
+        for (entity, tile, _, source, tile_config) in tiles.iter() {
+            if tile.stream != stream_entity {
+                continue;
+            }
+            let wanted_config = match wanted.get(&tile.coord) {
+                Some(&wanted_config)
+                    if !stream_tracker.is_changed()
+                        && source.seed == config.seed
+                        && loaded.insert(tile.coord) =>
+                {
+                    wanted_config
+                }
+                _ => {
+                    free.push(entity);
+                    continue;
+                }
+            };
+
+            // the camera moved the tile into another ring
+            if config_tracker.is_changed() || !same_detail(tile_config, wanted_config) {
+                commands.entity(entity).insert(wanted_config.clone());
+            }
+        }
+
+        let base = transform.translation().y;
+        for (&coord, &tile_config) in wanted.iter().filter(|(coord, _)| !loaded.contains(*coord)) {
+            let tile = GrassTile {
+                stream: stream_entity,
+                coord,
+            };
+            let tile_transform = Transform::from_xyz(
+                coord.x as f32 * stream.tile_size,
+                base,
+                coord.y as f32 * stream.tile_size,
+            );
+            let source = GrassSource {
+                vertices: Arc::new(tile_roots(stream, coord, base, config.seed)),
+                seed: config.seed,
+            };
+
+            match free.pop() {
+                Some(entity) => {
+                    let (_, mut old_tile, mut old_transform, mut old_source, old_config) =
+                        tiles.get_mut(entity).unwrap();
+                    if config_tracker.is_changed() || !same_detail(old_config, tile_config) {
+                        commands.entity(entity).insert(tile_config.clone());
+                    }
+                    *old_tile = tile;
+                    *old_transform = tile_transform;
+                    *old_source = source;
+                }
+                None => {
+                    commands
+                        .spawn()
+                        .insert(Grass)
+                        .insert(tile_config.clone())
+                        .insert(tile)
+                        .insert(source)
+                        .insert(tile_transform)
+                        .insert(GlobalTransform::from(tile_transform));
+                }
+            }
+        }
+
+        for entity in free {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Config of a tile `ring` tiles away from the one under the camera,
+/// capped to the most detailed lod band its roots can fall into,
+/// `None` if the tile lies beyond the last band.
+fn tile_config(config: &GrassConfig, tile_size: f32, ring: i32) -> Option<GrassConfig> {
+    if config.lod_bands.is_empty() {
+        return Some(config.clone());
+    }
+
+    // the camera may be anywhere in the tile under it
+    let nearest = (ring - 1).max(0) as f32 * tile_size;
+    let (segments, blades) = config
+        .lod_bands
+        .iter()
+        .filter(|lod| lod.distance > nearest)
+        .fold((0, 0), |(segments, blades), lod| {
+            (segments.max(lod.segments), blades.max(lod.blades))
+        });
+    if blades == 0 {
+        return None;
+    }
+
+    let mut config = config.clone();
+    config.segments = config.segments.min(segments);
+    config.blades = config.blades.min(blades);
+    for lod in &mut config.lod_bands {
+        lod.segments = lod.segments.min(config.segments);
+        lod.blades = lod.blades.min(config.blades);
+    }
+    Some(config)
+}
+
+/// The output buffers of both configs have the same size.
+fn same_detail(a: &GrassConfig, b: &GrassConfig) -> bool {
+    a.segments == b.segments && a.blades == b.blades
+}
+
+/// Roots of a tile, in the local space of the tile with its corner at the origin.
+fn tile_roots(
+    stream: &GrassStreaming,
+    coord: IVec2,
+    base: f32,
+    seed: u32,
+) -> Vec<GrassSourceVertex> {
+    const EPSILON: f32 = 0.05;
+
+    let size = stream.tile_size;
+    let origin = coord.as_vec2() * size;
+    let height = |point: Vec2| {
+        stream
+            .height
+            .as_ref()
+            .map_or(base, |ground| ground(origin + point))
+    };
+
+    let count = (stream.density * size * size).round() as usize;
+    let key = ((coord.x as u32 as u64) << 32) | coord.y as u32 as u64;
+    let mut rng = Rng(key ^ (seed as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+    (0..count)
+        .map(|_| {
+            let point = Vec2::new(rng.next_f32(), rng.next_f32()) * size;
+
+            let normal = match stream.height {
+                Some(_) => {
+                    let dx = height(point + Vec2::X * EPSILON) - height(point - Vec2::X * EPSILON);
+                    let dz = height(point + Vec2::Y * EPSILON) - height(point - Vec2::Y * EPSILON);
+                    Vec3::new(-dx, 2.0 * EPSILON, -dz).normalize()
+                }
+                None => Vec3::Y,
+            };
+
+            GrassSourceVertex {
+                position: [point.x, height(point) - base, point.y],
+                normal: normal.into(),
+                uv: (point / size).into(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(coord: IVec2, seed: u32) -> Vec<[f32; 8]> {
+        tile_roots(&GrassStreaming::default(), coord, 0.0, seed)
+            .iter()
+            .map(|vertex| bytemuck::cast(*vertex))
+            .collect()
+    }
+
+    #[test]
+    fn tile_roots_are_deterministic() {
+        let coord = IVec2::new(-3, 7);
+        assert!(!roots(coord, 1).is_empty());
+        assert_eq!(roots(coord, 1), roots(coord, 1));
+        assert_ne!(roots(coord, 1), roots(coord, 2));
+        assert_ne!(roots(coord, 1), roots(IVec2::new(7, -3), 1));
+    }
+
+    #[test]
+    fn tile_config_follows_the_lod_bands() {
+        let config = GrassConfig::default();
+        let near = tile_config(&config, 8.0, 1).unwrap();
+        assert_eq!((near.segments, near.blades), (5, 5));
+
+        let far = tile_config(&config, 8.0, 4).unwrap();
+        assert_eq!((far.segments, far.blades), (3, 3));
+        assert!(far.validate().is_ok());
+
+        assert!(tile_config(&config, 8.0, 8).is_none());
+    }
+}
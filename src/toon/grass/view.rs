@@ -0,0 +1,150 @@
+use bevy::{
+    prelude::*,
+    render::{
+        primitives::Frustum,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use bytemuck::{Pod, Zeroable};
+
+use super::occlusion::GrassDepthPyramid;
+
+/// Upper bound of cameras the grass is culled against, the rest are ignored.
+pub const MAX_VIEWS: usize = 4;
+/// Upper bound of shadow casting lights the grass is culled against, the rest are ignored.
+pub const MAX_SHADOW_VIEWS: usize = 8;
+
+/// Active 3d cameras and shadow casting lights of this frame.
+///
+/// The compute pass fills one set of blades shared by all of them,
+/// so a root is kept as long as any of the cameras can see it,
+/// or any of the lights for fields casting shadows.
+#[derive(Default)]
+pub struct GrassViews {
+    pub entities: Vec<Entity>,
+    pub frustums: Vec<[Vec4; 6]>,
+    pub positions: Vec<Vec3>,
+    pub shadow_frustums: Vec<[Vec4; 6]>,
+}
+
+pub fn extract_grass_views(
+    mut commands: Commands,
+    cameras: Extract<Query<(Entity, &Camera, &Frustum, &GlobalTransform), With<Camera3d>>>,
+    directional_lights: Extract<Query<(&DirectionalLight, &Frustum)>>,
+    spot_lights: Extract<Query<(&SpotLight, &Frustum)>>,
+    point_lights: Extract<Query<(&PointLight, &GlobalTransform)>>,
+) {
+    let mut views = GrassViews::default();
+    for (entity, camera, frustum, transform) in cameras.iter() {
+        if camera.is_active {
+            views.entities.push(entity);
+            views
+                .frustums
+                .push(frustum.planes.map(|plane| plane.normal_d()));
+            views.positions.push(transform.translation());
+        }
+    }
+
+    for (light, frustum) in directional_lights.iter() {
+        if light.shadows_enabled {
+            views
+                .shadow_frustums
+                .push(frustum.planes.map(|plane| plane.normal_d()));
+        }
+    }
+    for (light, frustum) in spot_lights.iter() {
+        if light.shadows_enabled {
+            views
+                .shadow_frustums
+                .push(frustum.planes.map(|plane| plane.normal_d()));
+        }
+    }
+    // the six faces of the cubemap cover the box around the range of the light
+    for (light, transform) in point_lights.iter() {
+        if light.shadows_enabled {
+            let center = transform.translation();
+            views.shadow_frustums.push(
+                [
+                    Vec3::X,
+                    Vec3::NEG_X,
+                    Vec3::Y,
+                    Vec3::NEG_Y,
+                    Vec3::Z,
+                    Vec3::NEG_Z,
+                ]
+                .map(|normal| normal.extend(light.range - normal.dot(center))),
+            );
+        }
+    }
+
+    commands.insert_resource(views);
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GrassViewsUniform {
+    count: u32,
+    /// The depth pyramid of the previous frame can be tested against.
+    occlusion: u32,
+    /// Shadow casting lights, their frustums follow the ones of the cameras.
+    shadow_count: u32,
+    _pad: u32,
+
+    /// Frustum planes as `(normal, distance)`, six per view.
+    planes: [[f32; 4]; 6 * (MAX_VIEWS + MAX_SHADOW_VIEWS)],
+    positions: [[f32; 4]; MAX_VIEWS],
+
+    /// View projection the depth pyramid was rendered with.
+    occlusion_view_proj: [[f32; 4]; 4],
+}
+
+/// Uniform buffer with the views, shared by every grass field.
+pub struct GrassViewsBuffer {
+    pub buffer: Buffer,
+}
+
+impl FromWorld for GrassViewsBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grass_views"),
+            size: std::mem::size_of::<GrassViewsUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer }
+    }
+}
+
+pub fn prepare_grass_views(
+    views: Res<GrassViews>,
+    pyramid: Res<GrassDepthPyramid>,
+    buffer: Res<GrassViewsBuffer>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut uniform = GrassViewsUniform::zeroed();
+
+    let frustums = views.frustums.iter().take(MAX_VIEWS);
+    let shadow_frustums = views.shadow_frustums.iter().take(MAX_SHADOW_VIEWS);
+    let frustums = frustums.chain(shadow_frustums);
+    for (planes, frustum) in uniform.planes.chunks_exact_mut(6).zip(frustums) {
+        for (dst, plane) in planes.iter_mut().zip(frustum) {
+            *dst = plane.to_array();
+        }
+    }
+    let positions = views.positions.iter().take(MAX_VIEWS);
+    for (dst, position) in uniform.positions.iter_mut().zip(positions) {
+        *dst = position.extend(1.0).to_array();
+    }
+    uniform.count = views.frustums.len().min(MAX_VIEWS) as u32;
+    uniform.shadow_count = views.shadow_frustums.len().min(MAX_SHADOW_VIEWS) as u32;
+
+    // roots hidden from one camera may still be seen by another
+    uniform.occlusion = (pyramid.ready && uniform.count == 1) as u32;
+    uniform.occlusion_view_proj = pyramid.previous_view_proj.to_cols_array_2d();
+
+    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&uniform));
+}
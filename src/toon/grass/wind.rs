@@ -0,0 +1,170 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use bytemuck::{Pod, Zeroable};
+use noise::{NoiseFn, Perlin};
+
+/// Wind shared by every grass field.
+///
+/// Gusts roll across the fields as waves along `direction`,
+/// the turbulence texture scrolls with the wind and adds small sideways sways.
+#[derive(Clone)]
+pub struct Wind {
+    /// World space direction, normalized before it is sent to the gpu.
+    pub direction: Vec3,
+    pub strength: f32,
+    /// Speed the gusts and the turbulence travel at, in units per second.
+    pub speed: f32,
+
+    /// Gusts per second passing a point.
+    pub gust_frequency: f32,
+    pub gust_amplitude: f32,
+
+    /// Tiling noise texture, red and green are the sideways offsets.
+    /// A default one is generated if unset at startup.
+    pub turbulence: Option<Handle<Image>>,
+    /// Size of one turbulence tile in world units.
+    pub turbulence_scale: f32,
+    pub turbulence_strength: f32,
+}
+
+impl Default for Wind {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(1.0, 0.0, 0.3),
+            strength: 1.0,
+            speed: 2.0,
+
+            gust_frequency: 0.25,
+            gust_amplitude: 1.5,
+
+            turbulence: None,
+            turbulence_scale: 8.0,
+            turbulence_strength: 0.75,
+        }
+    }
+}
+
+impl ExtractResource for Wind {
+    type Source = Self;
+
+    fn extract_resource(wind: &Self::Source) -> Self {
+        wind.clone()
+    }
+}
+
+pub fn setup_turbulence(mut wind: ResMut<Wind>, mut images: ResMut<Assets<Image>>) {
+    if wind.turbulence.is_none() {
+        wind.turbulence = Some(images.add(turbulence_image(64)));
+    }
+}
+
+/// Two channels of perlin noise sampled on a 4d torus, so the image tiles.
+fn turbulence_image(size: u32) -> Image {
+    const RADIUS: f64 = 1.5;
+
+    let perlin = Perlin::new();
+    let sample = |u: f64, v: f64, offset: f64| {
+        let (u, v) = (u * std::f64::consts::TAU, v * std::f64::consts::TAU);
+        let noise = perlin.get([
+            u.cos() * RADIUS + offset,
+            u.sin() * RADIUS,
+            v.cos() * RADIUS,
+            v.sin() * RADIUS,
+        ]);
+        ((noise * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0) as u8
+    };
+
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let (u, v) = (x as f64 / size as f64, y as f64 / size as f64);
+            data.extend([sample(u, v, 0.0), sample(u, v, 17.0), 0, 255]);
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    );
+    image.sampler_descriptor.address_mode_u = AddressMode::Repeat;
+    image.sampler_descriptor.address_mode_v = AddressMode::Repeat;
+    image
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct WindUniform {
+    direction: [f32; 3],
+    strength: f32,
+    speed: f32,
+
+    gust_frequency: f32,
+    gust_amplitude: f32,
+
+    turbulence_scale: f32,
+    turbulence_strength: f32,
+    _pad: [f32; 3],
+}
+
+pub struct GrassWindBuffer {
+    pub buffer: Buffer,
+}
+
+impl FromWorld for GrassWindBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grass_wind"),
+            size: std::mem::size_of::<WindUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer }
+    }
+}
+
+pub fn prepare_wind(
+    wind: Res<Wind>,
+    images: Res<RenderAssets<Image>>,
+    buffer: Res<GrassWindBuffer>,
+    render_queue: Res<RenderQueue>,
+) {
+    // the white fallback texture would turn into a constant offset
+    let has_turbulence = wind
+        .turbulence
+        .as_ref()
+        .map_or(false, |handle| images.get(handle).is_some());
+
+    let uniform = WindUniform {
+        direction: wind.direction.normalize_or_zero().into(),
+        strength: wind.strength,
+        speed: wind.speed,
+
+        gust_frequency: wind.gust_frequency,
+        gust_amplitude: wind.gust_amplitude,
+
+        turbulence_scale: wind.turbulence_scale,
+        turbulence_strength: if has_turbulence {
+            wind.turbulence_strength
+        } else {
+            0.0
+        },
+        _pad: [0.0; 3],
+    };
+
+    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&uniform));
+}
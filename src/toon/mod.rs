@@ -4,7 +4,7 @@ pub mod normal_pass;
 pub mod outline;
 pub mod postprocess;
 
-pub use self::grass::GrassPlugin;
+pub use self::grass::{GrassDiagnosticsPlugin, GrassPlugin};
 pub use self::normal_pass::NormalPassPlugin;
 pub use self::outline::OutlinePlugin;
 pub use self::postprocess::PostprocessPassPlugin;
@@ -13,7 +13,7 @@ use bevy::render::{
 use bevy::utils::HashMap;
 
 //const FORMAT: TextureFormat = TextureFormat::Rgba16Snorm;
-const FORMAT: TextureFormat = TextureFormat::Rgb10a2Unorm;
+pub const FORMAT: TextureFormat = TextureFormat::Rgb10a2Unorm;
 
 pub mod draw_normal_graph {
 